@@ -1,28 +1,311 @@
-from flask import Flask, make_response
+import argparse
+import json
+import os
+import sys
+from pathlib import Path
+from urllib.parse import urlsplit
 
-from tools.caching import CacheThread
+import arrow
+from flask import Flask
+
+from tools.blueprint import blueprint
+from tools.caching import CacheThread, cache_report, download, export_cache, fetch, healthcheck, import_cache, \
+    matching, purge, restore, set_cache_limits, set_dry_run, snapshot_time, snapshots
+from tools.completion import SHELLS, calendar_names, script, source_names
+from tools.config import config_files, read_config
+from tools.errors import FetchError, FusionError, ParseError
+from tools.fetchers import set_http_defaults
+from tools.logs import redact, setup_logging
+from tools.metrics import status_report
+from tools.reporting import setup_reporting
+from tools.storage import backend, open_backend, set_backend
 from tools.tools import *
+from tools.validation import lint, score, serialize, validate
 
 app = Flask(__name__)
+app.register_blueprint(blueprint)
+
+
+def serve(args: argparse.Namespace) -> None:
+    app.config["AUDIT_LOG"] = args.audit_log
+    app.config["REFRESH_TOKEN"] = args.refresh_token
+
+    thread = CacheThread(dry_run=args.dry_run)
+    thread.start()
+
+    app.run(host='0.0.0.0', port=8088)
+
+
+def print_completion(args: argparse.Namespace) -> None:
+    if args.names == "calendars":
+        print("\n".join(calendar_names()))
+    elif args.names == "sources":
+        print("\n".join(source_names()))
+    else:
+        sys.stdout.write(script(args.parser, args.shell, args.command))
+
+
+def explain_event(args: argparse.Namespace) -> None:
+    conf = args.calendar + ".json" if args.calendar is not None else None
+    lines = explain(args.query, conf)
+
+    if not lines:
+        print("No event matches " + args.query)
+        sys.exit(1)
+
+    print("\n".join(lines))
+
+
+def show_status(args: argparse.Namespace) -> None:
+    print("\n".join(status_report()))
+
+
+def check_health(args: argparse.Namespace) -> None:
+    healthy, lines = healthcheck()
 
+    print("\n".join(lines))
+    sys.exit(0 if healthy else 1)
 
-@app.route('/<calendar>')
-def main(calendar):
-    conf = calendar + ".json"
 
-    print("Opening " + conf)
+def selftest(args: argparse.Namespace) -> None:
+    valid = True
+
+    for conf in config_files():
+        try:
+            problems = validate(serialize(process(conf)))
+        except FileNotFoundError:
+            problems = ["calendar not cached"]
+        except FusionError as e:
+            problems = [redact(str(e))]
+
+        valid = valid and not problems
+        print(("OK " if not problems else "INVALID ") + conf[:-len(".json")])
+        for problem in problems:
+            print("  " + problem)
+
+    sys.exit(0 if valid else 1)
+
+
+def list_cache(args: argparse.Namespace) -> None:
+    print("\n".join(cache_report()) or "The cache is empty")
+
+
+def purge_cache(args: argparse.Namespace) -> None:
+    if args.source is None and not args.all:
+        print("Give the source to purge, or --all to empty the cache", file=sys.stderr)
+        sys.exit(2)
+
+    print("Removed " + str(purge(args.source)) + " files from the cache")
+
+
+def vacuum_cache(args: argparse.Namespace) -> None:
+    backend().vacuum()
+
+
+def list_snapshots(args: argparse.Namespace) -> None:
+    for calendar in matching(args.source):
+        print(calendar + ":")
+        for key in snapshots(calendar):
+            print("  " + str(snapshot_time(key)) + " (" + arrow.get(snapshot_time(key)).isoformat() + "): "
+                  + str(backend().meta(key)["size"]) + " bytes")
+
+
+def restore_snapshot(args: argparse.Namespace) -> None:
+    calendars = matching(args.source)
+    if len(calendars) != 1:
+        print(args.source + " designates " + str(len(calendars)) + " calendars, give the identifier of one of them",
+              file=sys.stderr)
+        sys.exit(2)
 
     try:
-        result = str(process(conf))
-        response = make_response(result, 200)
-        response.headers["Content-Disposition"] = "attachment; filename=calendar.ics"
-    except FileNotFoundError:
-        response = make_response("Calendar not cached", 425)
+        restore(calendars[0], args.time)
+    except KeyError as e:
+        print(e.args[0], file=sys.stderr)
+        sys.exit(1)
+
+
+def export_archive(args: argparse.Namespace) -> None:
+    print("Exported " + str(export_cache(args.archive)) + " files to " + args.archive)
+
+
+def import_archive(args: argparse.Namespace) -> None:
+    try:
+        print("Imported " + str(import_cache(args.archive)) + " files from " + args.archive)
+    except (OSError, ValueError) as e:
+        print("Could not import the archive: " + redact(str(e)), file=sys.stderr)
+        sys.exit(1)
+
+
+def merge_sources(args: argparse.Namespace) -> None:
+    options = json.loads(args.transforms) if args.transforms else {}
+    sources = args.sources + (["-"] if args.stdin else [])
+
+    cals = []
+    for source in sources:
+        # The paths given on the command line are read as file:// URLs
+        url = source if source == "-" or urlsplit(source).scheme else Path(source).absolute().as_uri()
+        entry = dict(options, url=url, name=source)
+        try:
+            cal = fetch(entry)
+        except (FusionError, ValueError) as e:
+            print("Could not retrieve " + redact(str(e)), file=sys.stderr)
+            sys.exit(1)
+
+        for _, transform in pipeline(entry):
+            cal = transform.apply(cal)
+        cals.append(cal)
+
+    sys.stdout.write(str(merge(cals)))
+
+
+def lint_sources(args: argparse.Namespace) -> None:
+    report = []
+
+    for conf in config_files():
+        for entry in read_config(conf):
+            try:
+                findings = lint(download(entry))
+            except ParseError:
+                findings = [{"check": "encoding", "detail": "the calendar could not be decoded",
+                             "suggestion": "set the 'encoding' option of the source"}]
+            except FetchError as e:
+                findings = [{"check": "fetch", "detail": redact(str(e)), "suggestion": "check the 'url' of the source"}]
+
+            report.append({"calendar": conf[:-len(".json")], "source": entry["name"], "score": score(findings),
+                           "findings": findings})
+
+    if args.json:
+        print(json.dumps(report, indent=4))
+        return
+
+    for source in report:
+        print(source["calendar"] + "/" + source["source"] + ": " + str(source["score"]) + "/100")
+        for finding in source["findings"]:
+            print("  " + finding["check"] + ": " + finding["detail"])
+            print("    suggestion: " + finding["suggestion"])
+
+
+if __name__ == '__main__':
+    parser = argparse.ArgumentParser(description="Merge multiple ics feeds into a single ics calendar")
+    parser.add_argument("--dry-run", action="store_true",
+                        help="download and merge the calendars without writing anything to the cache")
+    parser.add_argument("--log-format", choices=["text", "json"], default="text", help="format of the logs")
+    parser.add_argument("--log-level", default="info",
+                        help="comma separated log levels, global or per module (e.g. info,tools.caching=debug)")
+    parser.add_argument("--sentry-dsn", default=os.environ.get("SENTRY_DSN"),
+                        help="DSN of a Sentry compatible service to report the errors to (default: $SENTRY_DSN)")
+    parser.add_argument("--report-after", type=int, default=3,
+                        help="number of consecutive failed downloads of a source before reporting them")
+    parser.add_argument("--cache-url", default=os.environ.get("CACHE_URL", "app/cache"),
+                        help="where the cached calendars are stored: the path of a directory, sqlite:///path, "
+                             "memory:// or the redis:// URL of a Redis database shared by several instances "
+                             "(default: $CACHE_URL or app/cache)")
+    parser.add_argument("--cache-key", default=os.environ.get("CACHE_KEY"),
+                        help="encrypt the cache with this key, 32 bytes encoded in base64 (default: $CACHE_KEY)")
+    parser.add_argument("--cache-plaintext", action="store_true",
+                        help="with --cache-key, also read the files cached before the encryption was enabled")
+    parser.add_argument("--cache-max-entries", type=int,
+                        help="maximum number of calendars in the cache, the least recently used ones being removed")
+    parser.add_argument("--cache-max-size", type=float,
+                        help="maximum size of the cache in megabytes, the least recently used calendars being removed")
+    parser.add_argument("--audit-log", help="append every generated calendar to this JSON lines file")
+    parser.add_argument("--concurrency", type=int, default=8,
+                        help="maximum number of sources of a calendar downloaded at the same time")
+    parser.add_argument("--connect-timeout", type=float, default=10,
+                        help="maximum duration of the connection to a remote, in seconds")
+    parser.add_argument("--read-timeout", type=float, default=60,
+                        help="maximum duration without receiving data from a remote, in seconds")
+    parser.add_argument("--attempts", type=int, default=1,
+                        help="number of attempts to download a source before giving up")
+    parser.add_argument("--min-interval", type=float, default=0,
+                        help="minimum interval between two requests to the same host, in seconds")
+    parser.add_argument("--max-size", type=float, default=100,
+                        help="maximum size of the calendars downloaded, in megabytes (0 for no limit)")
+    parser.add_argument("--user-agent", help="User-Agent header of the requests to the remotes (default: ics-fusion "
+                                             "and its version)")
+    parser.add_argument("--contact", help="email address sent in the From header of the requests to the remotes")
+    parser.add_argument("--proxy", help="URL of the proxy used to download the sources (default: $HTTP_PROXY and "
+                                        "$HTTPS_PROXY, except for the hosts in $NO_PROXY)")
+    parser.add_argument("--refresh-token", default=os.environ.get("REFRESH_TOKEN"),
+                        help="token allowing the requests to refresh the cached sources (default: $REFRESH_TOKEN)")
+    parser.set_defaults(func=serve)
+    commands = parser.add_subparsers(title="commands")
+
+    completion_parser = commands.add_parser("completions", help="write the completion script of a shell")
+    completion_parser.add_argument("shell", nargs="?", choices=SHELLS, default="bash",
+                                   help="shell of the script (default: bash)")
+    completion_parser.add_argument("--command", help="name of the command to complete (default: server.py)")
+    # Used by the scripts to list the names of the calendars and of the sources when completing them
+    completion_parser.add_argument("--names", choices=["calendars", "sources"], help=argparse.SUPPRESS)
+    completion_parser.set_defaults(func=print_completion, parser=parser)
+
+    explain_parser = commands.add_parser("explain", help="trace an event through the filters and modifications")
+    explain_parser.add_argument("query", help="UID of the event, or RegEx searched in the name of the events")
+    explain_parser.add_argument("--calendar", help="only consider this calendar (name of the config file without "
+                                                   "the .json extension)")
+    explain_parser.set_defaults(func=explain_event)
+
+    status_parser = commands.add_parser("status", help="show the last download of every source")
+    status_parser.set_defaults(func=show_status)
+
+    healthcheck_parser = commands.add_parser("healthcheck", help="check the freshness of the cached calendars")
+    healthcheck_parser.set_defaults(func=check_health)
+
+    selftest_parser = commands.add_parser("selftest", help="generate every calendar and validate the result")
+    selftest_parser.set_defaults(func=selftest)
+
+    merge_parser = commands.add_parser("merge", help="merge calendars given on the command line and write the result "
+                                                     "on the standard output")
+    merge_parser.add_argument("sources", nargs="*", help="URLs or paths of the calendars, - for the standard input")
+    merge_parser.add_argument("--stdin", action="store_true", help="also merge the calendar read on the standard input")
+    merge_parser.add_argument("--transforms", help="JSON object with the filters, modify... fields of the config "
+                                                   "file, applied to every calendar")
+    merge_parser.set_defaults(func=merge_sources)
+
+    cache_parser = commands.add_parser("cache", help="inspect and clean the cache")
+    cache_commands = cache_parser.add_subparsers(dest="cache_command")
+    cache_commands.required = True
+
+    cache_list_parser = cache_commands.add_parser("list", help="show the files of every calendar in the cache")
+    cache_list_parser.set_defaults(func=list_cache)
+
+    cache_purge_parser = cache_commands.add_parser("purge", help="remove a calendar, or every calendar, from the cache")
+    cache_purge_parser.add_argument("source", nargs="?", help="identifier of the calendar, or name of a source using "
+                                                              "it (config file/source or source)")
+    cache_purge_parser.add_argument("--all", action="store_true", help="remove every calendar")
+    cache_purge_parser.set_defaults(func=purge_cache)
+
+    cache_vacuum_parser = cache_commands.add_parser("vacuum", help="reclaim the space freed in the SQLite database")
+    cache_vacuum_parser.set_defaults(func=vacuum_cache)
+
+    cache_snapshots_parser = cache_commands.add_parser("snapshots", help="list the snapshots of a calendar")
+    cache_snapshots_parser.add_argument("source", help="identifier of the calendar, or name of a source using it")
+    cache_snapshots_parser.set_defaults(func=list_snapshots)
+
+    cache_restore_parser = cache_commands.add_parser("restore", help="serve a snapshot of a calendar again")
+    cache_restore_parser.add_argument("source", help="identifier of the calendar, or name of a source using it")
+    cache_restore_parser.add_argument("time", type=int, help="time of the snapshot, as listed by cache snapshots")
+    cache_restore_parser.set_defaults(func=restore_snapshot)
 
-    return response
+    cache_export_parser = cache_commands.add_parser("export", help="write the whole cache to an archive")
+    cache_export_parser.add_argument("archive", help="path of the archive")
+    cache_export_parser.set_defaults(func=export_archive)
 
+    cache_import_parser = cache_commands.add_parser("import", help="add the files of an archive to the cache")
+    cache_import_parser.add_argument("archive", help="path of an archive written by cache export")
+    cache_import_parser.set_defaults(func=import_archive)
 
-thread = CacheThread()
-thread.start()
+    lint_parser = commands.add_parser("lint", help="report the quality of every source")
+    lint_parser.add_argument("--json", action="store_true", help="write the report as JSON")
+    lint_parser.set_defaults(func=lint_sources)
 
-app.run(host='0.0.0.0', port=8088)
+    args = parser.parse_args()
+    setup_logging(args.log_format == "json", args.log_level)
+    setup_reporting(args.sentry_dsn, args.report_after)
+    set_concurrency(args.concurrency)
+    set_backend(open_backend(args.cache_url, args.cache_key, args.cache_plaintext))
+    set_cache_limits(args.cache_max_entries, args.cache_max_size)
+    set_dry_run(args.dry_run)
+    set_http_defaults(args.connect_timeout, args.read_timeout, args.attempts, args.proxy, args.min_interval,
+                      args.max_size, args.user_agent, args.contact)
+    args.func(args)
@@ -0,0 +1,95 @@
+"""This module raises an alert when the download of a source has been failing for too long, and signals when the
+data of a source is older than its freshness SLA.
+
+The alert is configured per source in the config file:
+
+"alert":{
+    "after":60,
+    "webhook":"url"
+}
+
+- after: number of minutes a source must have been failing before raising the alert
+- webhook: if present, URL to which the alert is posted as JSON
+
+The alert is logged as an error, and the recovery of the source is logged as well.  Both are posted to the
+webhook, if any.
+
+The freshness SLA is set per source with the "freshnessSla" field, in minutes.  When the last successful download
+of the source is older than the SLA, it is logged as an error and posted to the webhook of the alert, if any.
+"""
+
+import logging
+
+import arrow
+import requests
+
+from tools.clock import now
+from tools.fetchers import transport
+
+logger = logging.getLogger(__name__)
+
+
+def notify(entry: dict, event: str, status: dict) -> None:
+    """Post an alert or a recovery to the webhook of the entry, if any
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param event: either "alert", "recovery" or "stale"
+    :type event: str
+
+    :param status: the status of the entry
+    :type status: dict
+    """
+
+    webhook = entry.get("alert", {}).get("webhook")
+    if webhook is None:
+        return
+
+    payload = {"event": event, "source": entry["name"], "failingSince": status.get("failingSince"),
+               "error": status.get("error"), "consecutiveFailures": status.get("consecutiveFailures")}
+    try:
+        transport().post(webhook, json=payload, timeout=10)
+    except requests.RequestException as e:
+        logger.warning("Could not post the %s of %s to the webhook: %s", event, entry["name"], e)
+
+
+def check_alert(entry: dict, status: dict) -> None:
+    """Raise the alert of an entry if it has been failing for longer than its threshold, or signal its recovery.
+    Signal as well when the data of the entry exceeds its freshness SLA.
+    The status is updated to remember that the alert was raised
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param status: the status of the entry, updated with the last download
+    :type status: dict
+    """
+
+    if "freshnessSla" in entry and "lastSuccess" in status:
+        stale = (now() - arrow.get(status["lastSuccess"])).total_seconds() > entry["freshnessSla"] * 60
+        if stale and not status.get("staleNotified"):
+            logger.error("%s exceeds its freshness SLA, last updated at %s", entry["name"], status["lastSuccess"])
+            notify(entry, "stale", status)
+            status["staleNotified"] = True
+        elif not stale:
+            status.pop("staleNotified", None)
+
+    if "alert" not in entry:
+        return
+
+    if status.get("error") is None:
+        if status.pop("alerted", False):
+            logger.info("%s recovered", entry["name"])
+            notify(entry, "recovery", status)
+        return
+
+    failing = (now() - arrow.get(status["failingSince"])).total_seconds() / 60
+    if not status.get("alerted") and failing >= entry["alert"].get("after", 0):
+        logger.error("%s has been failing since %s: %s", entry["name"], status["failingSince"], status["error"])
+        notify(entry, "alert", status)
+        status["alerted"] = True
@@ -0,0 +1,38 @@
+"""This module keeps an append-only audit log of the generated calendars.
+
+Every generation is written as one JSON object per line, with the sources used, whether they came from the cache or
+from the remote, their number of events and the hash of the served calendar.
+"""
+
+import json
+import threading
+from hashlib import sha256
+from typing import List
+
+from tools.clock import now
+
+_lock = threading.Lock()
+
+
+def write_audit(path: str, calendar: str, sources: List[dict], result: str) -> None:
+    """Append the generation of a calendar to the audit log
+
+
+    :param path: path of the audit log
+    :type path: str
+
+    :param calendar: name of the generated calendar
+    :type calendar: str
+
+    :param sources: the description of the sources used, as reported by the process function
+    :type sources: List[dict]
+
+    :param result: the generated calendar, as served
+    :type result: str
+    """
+
+    record = {"time": now().isoformat(), "calendar": calendar, "sources": sources,
+              "hash": sha256(result.encode()).hexdigest()}
+
+    with _lock, open(path, 'a') as file:
+        file.write(json.dumps(record) + "\n")
@@ -0,0 +1,105 @@
+"""This module provides the HTTP endpoints of the application as a Flask blueprint, so they can be mounted in
+another Flask application, under any prefix and behind its own middlewares:
+
+app.register_blueprint(blueprint, url_prefix="/calendars")
+
+The calendars are only cached while a CacheThread runs: the host application is responsible for starting it.  The
+AUDIT_LOG setting of the application gives the path of the audit log, if any.  The REFRESH_TOKEN setting enables the
+refresh of the cached sources on demand, for the requests bearing this token.
+"""
+
+import hmac
+import logging
+
+from flask import Blueprint, current_app, make_response, request
+
+from tools.audit import write_audit
+from tools.caching import healthcheck, refresh
+from tools.config import read_config
+from tools.errors import FusionError
+from tools.logs import redact, span
+from tools.metrics import prometheus
+from tools.tools import process
+from tools.validation import serialize
+
+blueprint = Blueprint("ics_fusion", __name__)
+logger = logging.getLogger(__name__)
+
+
+@blueprint.route('/metrics')
+def metrics():
+    response = make_response(prometheus(), 200)
+    response.headers["Content-Type"] = "text/plain; version=0.0.4"
+    return response
+
+
+@blueprint.route('/health')
+def health():
+    healthy, lines = healthcheck()
+    response = make_response("\n".join(lines) + "\n", 200 if healthy else 503)
+    response.headers["Content-Type"] = "text/plain"
+    return response
+
+
+def refresh_requested() -> bool:
+    """Check whether the request asks for the cached sources to be downloaded again, with the refresh=1 query
+    parameter or the X-Refresh: 1 header
+
+
+    :return: True if a refresh is requested
+    :rtype: bool
+    """
+
+    return request.args.get("refresh") == "1" or request.headers.get("X-Refresh") == "1"
+
+
+def authorized() -> bool:
+    """Check whether the request bears the refresh token of the application, as a bearer token or in the token query
+    parameter
+
+
+    :return: True if the request bears the refresh token
+    :rtype: bool
+    """
+
+    expected = current_app.config.get("REFRESH_TOKEN")
+    if not expected:
+        return False
+
+    header = request.headers.get("Authorization", "")
+    given = header[len("Bearer "):] if header.startswith("Bearer ") else request.args.get("token", "")
+    return hmac.compare_digest(given.encode(), expected.encode())
+
+
+@blueprint.route('/<calendar>')
+def main(calendar):
+    conf = calendar + ".json"
+
+    with span("serve", calendar=calendar):
+        logger.info("Opening %s", conf)
+
+        if refresh_requested():
+            if not authorized():
+                return make_response("Refresh not allowed", 403)
+
+            try:
+                for entry in read_config(conf):
+                    refresh(entry)
+            except FileNotFoundError:
+                pass
+
+        try:
+            sources = []
+            result = serialize(process(conf, sources))
+            if current_app.config.get("AUDIT_LOG"):
+                write_audit(current_app.config["AUDIT_LOG"], calendar, sources, result)
+
+            response = make_response(result, 200)
+            response.headers["Content-Disposition"] = "attachment; filename=calendar.ics"
+        except FileNotFoundError:
+            response = make_response("Calendar not cached", 425)
+        except FusionError as e:
+            logger.error("Could not generate %s: %s", calendar, e)
+            response = make_response("Could not retrieve " + redact(str(e)), 502)
+
+        return response
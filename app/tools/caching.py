@@ -1,22 +1,251 @@
+import gzip
 import json
-import os
+import logging
+import re
 import sched
 import threading
 import time
 from hashlib import sha256
+from typing import List, Tuple
 
 import traceback
 import arrow
-import requests
 from ics import Calendar
-from tatsu.exceptions import FailedParse
 
+from tools.alerts import check_alert
+from tools.clock import now
+from tools.config import config_files, read_config
+from tools.errors import FetchError, FusionError, NotCachedError, NotModified, ParseError
+from tools.fetchers import get_fetcher
+from tools.hooks import dispatch
+from tools.logs import redact, span
+from tools.properties import parse_calendar
+from tools.reporting import report_failure
+from tools.storage import backend
+from tools.timezones import definitions, normalize
 
-def cache(entry: dict, scheduler: sched.scheduler = None) -> None:
-    """Cache an .ics feed in the app/cache directory.
+logger = logging.getLogger(__name__)
+
+ARCHIVE = "ics-fusion-cache"
+# The names of the files of the cache: the identifier of the calendar followed by the kind of file
+KEY = re.compile(r"^[0-9a-f]{64}(?:\.ics|\.json|\.last\.ics|\.snapshot\.\d+\.ics)$")
+VEVENT = re.compile(r"^BEGIN:VEVENT\s*$.*?^END:VEVENT\s*$", re.MULTILINE | re.DOTALL | re.IGNORECASE)
+UID = re.compile(r"^UID[;:]", re.MULTILINE | re.IGNORECASE)
+DTSTAMP = re.compile(r"^DTSTAMP[;:].*$", re.MULTILINE | re.IGNORECASE)
+VJOURNAL = re.compile(r"^BEGIN:VJOURNAL\s*$.*?^END:VJOURNAL\s*$", re.MULTILINE | re.DOTALL | re.IGNORECASE)
+
+HISTORY = 20
+CHANGES = 50
+PRUNE_INTERVAL = 60
+
+# Time after the expiry of a cached calendar before a request downloads it again itself, in seconds.  The scheduler
+# downloads the cached calendars again at the end of their interval: only the calendars it is late for are renewed
+RENEW_GRACE = 60
+
+_revalidating = set()
+_revalidating_lock = threading.Lock()
+
+_limits = {"entries": None, "size": None}
+_accessed = {}
+_evicted = set()
+_dry_run = False
+
+
+def set_cache_limits(max_entries: int = None, max_size: float = None) -> None:
+    """Limit the size of the cache.  When a limit is exceeded, the least recently used calendars are removed from the
+    cache
+
+
+    :param max_entries: maximum number of calendars in the cache, unlimited if None
+    :type max_entries: int
+
+    :param max_size: maximum size of the cache in megabytes, unlimited if None
+    :type max_size: float
+    """
+
+    _limits.update({"entries": max_entries, "size": max_size})
+
+
+def set_dry_run(enabled: bool) -> None:
+    """Download and parse the calendars without writing anything to the cache, whichever part of the application
+    downloads them: the caching process, the requests of the clients or the commands
+
+
+    :param enabled: whether nothing is written to the cache
+    :type enabled: bool
+    """
+
+    global _dry_run
+    _dry_run = enabled
+
+
+def cache_key(entry: dict, extension: str) -> str:
+    """Compute the name of a file of the cache for an entry.  Different entries fetched from the same calendar share
+    the same files
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param extension: the extension of the file: .ics for the calendar, .json for its status
+    :type extension: str
+
+
+    :return: the name of the file
+    :rtype: str
+    """
+
+    return get_fetcher(entry).id() + extension
+
+
+def download(entry: dict, metrics: dict = None, validators: dict = None) -> str:
+    """Download the calendar of an entry with its fetcher and decode it with the encoding specified in the entry,
+    if any
+
+
+    :param entry: representation of the entry to download.  This is the Python representation of the corresponding
+    entry in the config file
+    :type entry: dict
+
+    :param metrics: if specified, the duration of the request and the size of the payload are added to it
+    :type metrics: dict
+
+    :param validators: if specified, the etag and the lastModified validators of the cached calendar, so the calendar
+    is only downloaded if it changed since then
+    :type validators: dict
+
+
+    :return: the content of the calendar, as served by the remote
+    :rtype: str
+
+
+    :raises FetchError: if the calendar cannot be retrieved
+    :raises ParseError: if the calendar cannot be decoded
+    :raises NotModified: if the calendar did not change since the download identified by the validators
+    """
+
+    with span("fetch", source=entry["name"]):
+        start = time.monotonic()
+        fetcher = get_fetcher(entry)
+        if validators:
+            fetcher.conditional(validators)
+
+        try:
+            return fetcher.fetch(metrics)
+        finally:
+            if metrics is not None:
+                metrics["fetchDuration"] = time.monotonic() - start
+
+
+def fetch(entry: dict, metrics: dict = None) -> Calendar:
+    """Download and parse the calendar of an entry
+
+
+    :param entry: representation of the entry to download.  This is the Python representation of the corresponding
+    entry in the config file
+    :type entry: dict
+
+    :param metrics: if specified, the duration of the request, the size of the payload, the parse duration, the
+    number of events, the number of recurring events and the date range covered by the events are added to it
+    :type metrics: dict
+
+
+    :return: the calendar served by the remote
+    :rtype: Calendar
+
+
+    :raises FetchError: if the calendar cannot be retrieved
+    :raises ParseError: if the calendar cannot be decoded or parsed
+    """
+
+    return parse(entry, download(entry, metrics), metrics)
+
+
+def parse(entry: dict, data: str, metrics: dict = None) -> Calendar:
+    """Parse the calendar of an entry
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry in the
+    config file
+    :type entry: dict
+
+    :param data: the content of the calendar, as served by the remote
+    :type data: str
+
+    :param metrics: if specified, the parse duration, the number of events, the number of recurring events and the
+    date range covered by the events are added to it
+    :type metrics: dict
+
+
+    :return: the parsed calendar
+    :rtype: Calendar
+
+
+    :raises ParseError: if the content is not a calendar or cannot be parsed
+    """
+
+    # An error page served with a success status must not replace the cached calendar
+    if "BEGIN:VCALENDAR" not in data[:1024].upper():
+        raise ParseError(entry["name"], "the remote did not serve a calendar")
+
+    start = time.monotonic()
+    data = identify(normalize(data))
+    try:
+        cal = parse_calendar(data)
+    except Exception as e:
+        raise ParseError(entry["name"], "could not parse the calendar: " + str(e)) from e
+
+    # Kept to be written in the merged calendar, which would otherwise not define them
+    cal.vtimezones = definitions(data)
+    # The journal entries are not supported by the parser, they are merged as is
+    cal.vjournals = ["\r\n".join(match.group(0).splitlines()) for match in VJOURNAL.finditer(data)]
+
+    if metrics is not None:
+        metrics["parseDuration"] = time.monotonic() - start
+        metrics["events"] = len(cal.events)
+        metrics["recurring"] = len([e for e in cal.events if any(line.name == "RRULE" for line in e.extra)])
+
+        dated = [e for e in cal.events if e.begin is not None]
+        if dated:
+            metrics["firstEvent"] = min(e.begin for e in dated).isoformat()
+            metrics["lastEvent"] = max(e.end if e.end is not None else e.begin for e in dated).isoformat()
+
+    return cal
+
+
+def identify(data: str) -> str:
+    """Give a UID to the events of a calendar that have none.  The UID is derived from the content of the event, so it
+    is the same every time the calendar is parsed, as long as the event does not change.  The DTSTAMP is left out,
+    since some remotes set it to the time of the download
+
+
+    :param data: the content of the calendar
+    :type data: str
+
+
+    :return: the content of the calendar, every event having a UID
+    :rtype: str
+    """
+
+    def replace(match) -> str:
+        event = match.group(0)
+        if UID.search(event) is not None:
+            return event
+
+        digest = sha256("\n".join(DTSTAMP.sub("", event).split()).encode()).hexdigest()
+        begin, rest = event.split("\n", 1)
+        ending = "\r\n" if begin.endswith("\r") else "\n"
+        return begin + "\nUID:" + digest + "@ics-fusion" + ending + rest
+
+    return VEVENT.sub(replace, data)
+
+
+def cache(entry: dict, scheduler: sched.scheduler = None, dry_run: bool = False) -> None:
+    """Cache an .ics feed in the backend of the cache, the app/cache directory by default.
     Different entries with the same URL will be cached in the same file.
-    The cached calendar contains a new line in the description with the current time when cached prefixed by the
-    'Cached at' mention
+    The calendar is cached as served by the remote, with its timezones normalized, so reading it from the cache gives
+    the same calendar as downloading it
 
 
 
@@ -27,44 +256,311 @@ def cache(entry: dict, scheduler: sched.scheduler = None) -> None:
     :param scheduler: scheduler used to relaunch the caching task in the future.  If not scheduler is specified,
     the task will not be relaunched
     :type scheduler: sched.scheduler
+
+    :param dry_run: if true, the calendar is downloaded and parsed but nothing is written to the disk.  This is
+    always the case once set_dry_run is enabled
+    :type dry_run: bool
+    """
+
+    dry_run = dry_run or _dry_run
+    metrics = {}
+
+    with span("cache", source=entry['name']):
+        try:
+            key = cache_key(entry, ".ics")
+            meta = backend().meta(key)
+            cached = backend().get(key) if meta is not None else None
+            # A cached calendar that cannot be read, e.g. encrypted with another key, is downloaded again in full
+            if cached is None:
+                meta = None
+            status = read_status(entry)
+            previous = status.get("metrics", {})
+            # After the restore of a snapshot, the cached calendar is not the one last served by the remote
+            served = status.get("restoredOver") or meta.get("contentHash") if meta is not None else None
+
+            # The validators are stored with the cached calendar, so they still match it after a failed download
+            try:
+                data = download(entry, metrics, meta)
+                metrics["contentHash"] = sha256(data.encode()).hexdigest()
+            except NotModified:
+                metrics["contentHash"] = served
+
+            # Only update the freshness of the cache when the remote serves the same content as the last time
+            if meta is not None and served == metrics["contentHash"]:
+                logger.info("%s is unchanged", entry['name'])
+                if not dry_run:
+                    backend().touch(key, dict(meta, **dict(metrics, contentHash=meta.get("contentHash"))))
+                    write_status(entry, metrics=dict(previous, **metrics))
+                return
+
+            cal = parse(entry, data, metrics)
+
+            anomaly = check_guard(entry, metrics)
+            if anomaly is not None:
+                logger.error("Keeping the previous data of %s: %s", entry['name'], anomaly)
+                if not dry_run:
+                    write_status(entry, anomaly)
+                return
+
+            if meta is not None:
+                changes(entry, cached, cal, metrics)
+
+            if dry_run:
+                logger.info("Would cache %s in %s (%d events)", entry['name'], key, len(cal.events))
+                return
+
+            backend().put(key, normalize(data), metrics)
+            snapshot(entry, normalize(data))
+            write_status(entry, metrics=metrics)
+            logger.info("Cached %s", entry['name'])
+
+        except FusionError as e:
+            logger.warning("Could not cache %s", e)
+            if not dry_run:
+                status = write_status(entry, str(e), metrics)
+                report_failure(entry, e, status["consecutiveFailures"])
+
+        # Save stack trace when an unknown error occurs
+        except Exception as e:
+            if dry_run:
+                logger.exception("Could not cache %s", entry['name'])
+                return
+
+            with open("error " + now().format("YYYY-MM-DD HH:mm:ss")+".txt", 'w') as file:
+                file.write(now().format("YYYY-MM-DD HH:mm:ss") + "\nCould not cache : " + redact(str(entry)))
+                file.write(redact(str(e)))
+                file.write(redact(str(traceback.format_exc())))
+            status = write_status(entry, str(e), metrics)
+            report_failure(entry, e, status["consecutiveFailures"])
+            logger.error("Could not cache %s: %s", entry['name'], e)
+        finally:
+            if scheduler is not None:
+                delay = entry['cache'] if entry['cache'] > 0 else 10
+                delay *= 60
+                scheduler.enter(delay=delay, priority=1, action=cache, argument=(entry, scheduler, dry_run))
+
+
+def changes(entry: dict, previous: str, cal: Calendar, metrics: dict) -> None:
+    """Log the events added, removed and modified between the cached calendar and the new version replacing it, and
+    record their UIDs in the metrics (at most CHANGES of each).  The occurrences overriding a recurring event are
+    identified by their UID and their RECURRENCE-ID
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param previous: the cached calendar
+    :type previous: str
+
+    :param cal: the new version of the calendar
+    :type cal: Calendar
+
+    :param metrics: the metrics of the download of the new version
+    :type metrics: dict
     """
 
+    def index(calendar: Calendar) -> dict:
+        events = {}
+        for event in calendar.events:
+            recurrence = next((line.value for line in event.extra if line.name == "RECURRENCE-ID"), None)
+            events[event.uid + (" " + recurrence if recurrence is not None else "")] = str(event)
+        return events
+
     try:
-        if not os.path.isdir('app/cache'):
-            os.mkdir('app/cache')
+        before = index(parse(entry, previous))
+    except ParseError:
+        logger.debug("Could not compare %s with its cached copy", entry['name'])
+        return
 
-        url = entry['url']
-        path = "app/cache/" + sha256(url.encode()).hexdigest() + ".ics"
+    after = index(cal)
+    found = {
+        "added": sorted(uid for uid in after if uid not in before),
+        "removed": sorted(uid for uid in before if uid not in after),
+        "modified": sorted(uid for uid in after if uid in before and after[uid] != before[uid]),
+    }
 
-        r = requests.get(entry["url"], allow_redirects=True)
+    logger.info("%s changed: %d events added, %d removed, %d modified", entry['name'], len(found["added"]),
+                len(found["removed"]), len(found["modified"]))
+    for change, uids in found.items():
+        for uid in uids:
+            logger.debug("%s: %s %s", entry['name'], change, uid)
 
-        if "encoding" in entry:
-            cal = Calendar(imports=r.content.decode(encoding=entry["encoding"]))
-        else:
-            cal = Calendar(imports=r.content.decode())
+    metrics["changes"] = {change: uids[:CHANGES] for change, uids in found.items()}
+    metrics["changes"]["time"] = now().isoformat()
 
-        cal = horodate(cal, 'Cached at')
-        open(path, 'w').writelines(cal)
-        print(arrow.now().format("YYYY-MM-DD HH:mm:ss"), "Cached", entry['name'])
 
-    except FailedParse:
-        print("Could not parse", entry['name'])
+def check_guard(entry: dict, metrics: dict) -> str:
+    """Check whether the number of events of a download dropped more than allowed by the guard of the entry, or to
+    zero, compared to the last accepted download
 
-    # Save stack trace when an unknown error occurs
-    except Exception as e:
-        with open("error " + arrow.now().format("YYYY-MM-DD HH:mm:ss")+".txt", 'w') as file:
-            file.write(arrow.now().format("YYYY-MM-DD HH:mm:ss") + "\nCould not cache : " + str(entry))
-            file.write(str(e))
-            file.write(str(traceback.format_exc()))
-    finally:
-        if scheduler is not None:
-            delay = entry['cache'] if entry['cache'] > 0 else 10
-            delay *= 60
-            scheduler.enter(delay=delay, priority=1, action=cache, argument=(entry, scheduler))
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param metrics: the metrics measured during the download
+    :type metrics: dict
+
+
+    :return: the description of the anomaly, None if the download is acceptable
+    :rtype: str
+    """
+
+    guard = entry.get("guard", {})
+    if "maxDrop" not in guard and not guard.get("rejectEmpty"):
+        return None
+
+    previous = read_status(entry).get("metrics", {}).get("events")
+    if not previous:
+        return None
+
+    if guard.get("rejectEmpty") and metrics["events"] == 0:
+        return "the calendar is empty while it had " + str(previous) + " events"
+
+    if "maxDrop" in guard and metrics["events"] < previous * (1 - guard["maxDrop"] / 100):
+        return "the number of events dropped from " + str(previous) + " to " + str(metrics["events"])
+
+    return None
+
+
+def read_status(entry: dict) -> dict:
+    """Read the status of the last download of the entry.  The status is stored next to the cached calendar
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the status of the entry, empty if the entry has never been downloaded
+    :rtype: dict
+    """
+
+    data = backend().get(cache_key(entry, ".json"))
+    return json.loads(data) if data is not None else {}
+
+
+def write_status(entry: dict, error: str = None, metrics: dict = None) -> dict:
+    """Save the status of the last download of the entry, count the consecutive failures and keep the outcome of the
+    last downloads.  If the entry has been failing for too long, an alert is raised.  The callbacks registered for
+    the success or the failure of the downloads are called
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param error: description of the error that occurred, None if the entry was downloaded successfully
+    :type error: str
+
+    :param metrics: the metrics measured during the download.  The difference between the number of events and the
+    number of events of the previous download is added to it
+    :type metrics: dict
+
+
+    :return: the saved status
+    :rtype: dict
+    """
+
+    current = now().isoformat()
+    status = read_status(entry)
+    status.update({"name": entry['name'], "lastRun": current, "error": redact(error) if error is not None else None})
+    if error is None:
+        status["lastSuccess"] = current
+        status["consecutiveFailures"] = 0
+        status.pop("failingSince", None)
+    else:
+        status["consecutiveFailures"] = status.get("consecutiveFailures", 0) + 1
+        status.setdefault("failingSince", current)
+
+    if metrics is not None:
+        if "contentHash" in metrics and metrics["contentHash"] != status.get("restoredOver"):
+            status.pop("restoredOver", None)
+
+        previous = status.get("metrics", {}).get("events")
+        if "events" in metrics and previous is not None:
+            metrics["delta"] = metrics["events"] - previous
+        status["metrics"] = metrics
+
+    history = status.get("history", [])
+    history.append({"time": current, "httpStatus": (metrics or {}).get("httpStatus"), "error": error})
+    status["history"] = history[-HISTORY:]
+
+    check_alert(entry, status)
+
+    if error is None:
+        dispatch("fetch_success", entry, metrics)
+    else:
+        dispatch("fetch_failure", entry, status["error"])
+
+    backend().put(cache_key(entry, ".json"), json.dumps(status))
+
+    return status
 
 
 def get_from_cache(entry: dict) -> Calendar:
-    """Retrieve the entry from cache.  If the entry is not found, an exception is raised
+    """Retrieve the entry from cache.  If the entry is not found, an exception is raised.  The cached calendar is
+    renewed first if it expired, see renew.
+    The calendar contains a new line in the description with the time when it was last cached prefixed by the
+    'Cached at' mention
+
+
+    :param entry: representation of the entry to cache.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the corresponding calendar in cache
+    :rtype: Calendar
+
+
+    :raises NotCachedError: if the entry has not been cached before
+    """
+
+    renew(entry)
+    return read_cache(entry)
+
+
+def renew(entry: dict) -> None:
+    """Record the use of a cached entry and, if its cached calendar expired more than RENEW_GRACE seconds ago,
+    download it again first, unless it was already attempted less than minRefresh minutes ago.  If it expired less
+    than maxStale minutes ago, it is downloaded again in the background instead, the stale copy being served meanwhile.
+    A calendar removed from the full cache by prune is downloaded again first
+
+
+    :param entry: representation of the entry to cache.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :raises NotCachedError: if the entry has not been cached before
+    """
+
+    _accessed[cache_key(entry, "")] = now().timestamp
+    if backend().meta(cache_key(entry, ".ics")) is None and cache_key(entry, "") in _evicted:
+        # The calendar was removed from the full cache while still configured: it is cached again once used
+        logger.info("%s was removed from the full cache, downloading it again", entry['name'])
+        _evicted.discard(cache_key(entry, ""))
+        cache(entry)
+
+    if backend().meta(cache_key(entry, ".ics")) is None:
+        logger.warning("%s is not cached", entry['name'])
+        raise NotCachedError(entry['name'], "the calendar is not cached")
+
+    remaining = freshness(entry)
+    if remaining is not None and remaining <= -RENEW_GRACE:
+        last = read_status(entry).get("lastRun")
+        if last is None or (now() - arrow.get(last)).total_seconds() >= entry.get("minRefresh", 1) * 60:
+            if -remaining < entry.get("maxStale", 0) * 60:
+                revalidate(entry)
+            else:
+                logger.info("%s expired %d seconds ago, downloading it again", entry['name'], -remaining)
+                cache(entry)
+
+
+def read_cache(entry: dict) -> Calendar:
+    """Parse the cached calendar of an entry as is, with the 'Cached at' mention in the description of its events
 
 
     :param entry: representation of the entry to cache.  This is the Python representation of the corresponding entry
@@ -76,24 +572,53 @@ def get_from_cache(entry: dict) -> Calendar:
     :rtype: Calendar
 
 
-    :raises FileNotfoundError: if the entry has not been cached before
+    :raises NotCachedError: if the entry has not been cached before
+    """
+
+    key = cache_key(entry, ".ics")
+    meta = backend().meta(key)
+    if meta is None:
+        raise NotCachedError(entry['name'], "the calendar is not cached")
+
+    data = backend().get(key)
+    if data is None:
+        raise NotCachedError(entry['name'], "the cached calendar cannot be read")
+
+    cal = parse(entry, data)
+    return horodate(cal, 'Cached at', arrow.get(meta["mtime"]).to(now().tzinfo))
+
+
+def revalidate(entry: dict) -> None:
+    """Download a cached entry again in the background, unless it is already being downloaded in the background
+
+
+    :param entry: representation of the entry to cache.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
     """
 
-    url = entry['url']
-    path = "app/cache/" + sha256(url.encode()).hexdigest() + ".ics"
-    if not os.path.isfile(path):
-        print("Not cached")
-        raise FileNotFoundError("The calendar is not cached")
+    key = cache_key(entry, ".ics")
+    with _revalidating_lock:
+        if key in _revalidating:
+            return
+        _revalidating.add(key)
 
-    with open(path, 'r') as file:
-        data = file.read()
+    def run():
+        try:
+            cache(entry)
+        finally:
+            with _revalidating_lock:
+                _revalidating.discard(key)
 
-    return Calendar(imports=data)
+    logger.info("Serving the stale copy of %s while downloading it again", entry['name'])
+    threading.Thread(target=run, daemon=True).start()
 
 
 def load_cal(entry: dict) -> Calendar:
     """Load the calendar from the cache or from remote according to the entry.  If the calendar is supposed to be in
-    cached but could not be found in cache, an error is thrown
+    cached but could not be found in cache, an error is thrown.  If the calendar cannot be retrieved from the remote,
+    the last copy downloaded successfully is used, with a mention in the description of its events.  In dry run, the
+    status and the last copy are not written
 
 
     :param entry: representation of the entry to cache.  This is the Python representation of the corresponding entry
@@ -105,26 +630,133 @@ def load_cal(entry: dict) -> Calendar:
     :rtype: Calendar
 
 
-    :raises FileNotfoundError: if the entry was supposed to be cached but has not been cached before
+    :raises NotCachedError: if the entry was supposed to be cached but has not been cached before
+    :raises FetchError: if the calendar cannot be retrieved from the remote and was never downloaded before
+    :raises ParseError: if the calendar served by the remote cannot be decoded or parsed and was never downloaded
+    before
     """
 
     if "cache" in entry and entry["cache"]:
-        print("Getting", entry["name"], "from cache")
+        logger.debug("Getting %s from cache", entry["name"])
         return get_from_cache(entry)
 
     else:
-        print("Getting", entry["name"], "from remote")
-        r = requests.get(entry["url"], allow_redirects=True)
-        if "encoding" in entry:
-            cal = Calendar(imports=r.content.decode(encoding=entry["encoding"]))
-        else:
-            cal = Calendar(imports=r.content.decode())
+        logger.debug("Getting %s from remote", entry["name"])
+        _accessed[cache_key(entry, "")] = now().timestamp
+        metrics = {}
+        last_good = cache_key(entry, ".last.ics")
+
+        error = recent_failure(entry)
+        if error is None:
+            try:
+                data = download(entry, metrics)
+                cal = parse(entry, data, metrics)
+            except Exception as e:
+                if not _dry_run:
+                    status = write_status(entry, str(e), metrics)
+                    report_failure(entry, e, status["consecutiveFailures"])
+                error = e
+
+        if error is not None:
+            meta = backend().meta(last_good)
+            copy = backend().get(last_good) if meta is not None else None
+            if not isinstance(error, FusionError) or copy is None:
+                raise error
+
+            # Keep the events of the source until it recovers, rather than silently dropping them
+            logger.warning("Using the last good copy of %s: %s", entry["name"], error)
+            cal = parse(entry, copy)
+            last = read_status(entry).get("lastSuccess")
+            return mark_stale(cal, arrow.get(last) if last is not None else arrow.get(meta["mtime"]).to(now().tzinfo))
+
+        metrics["contentHash"] = sha256(data.encode()).hexdigest()
+        if not _dry_run:
+            previous = read_status(entry).get("metrics", {}).get("contentHash")
+            write_status(entry, metrics=metrics)
+            # The last good copy is only written again when the remote serves a new content
+            if previous != metrics["contentHash"] or backend().meta(last_good) is None:
+                backend().put(last_good, data)
 
         cal = horodate(cal, 'Downloaded at')
         return cal
 
 
-def horodate(cal: Calendar, prefix='') -> Calendar:
+def peek(entry: dict) -> Calendar:
+    """Load the calendar of an entry without modifying the cache: the cached copy is used as is, even if it expired,
+    and the calendars that are not cached are downloaded without recording their status
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the calendar corresponding to the entry
+    :rtype: Calendar
+
+
+    :raises NotCachedError: if the entry was supposed to be cached but has not been cached before
+    :raises FetchError: if the calendar cannot be retrieved from the remote
+    :raises ParseError: if the calendar served by the remote cannot be decoded or parsed
+    """
+
+    if entry.get("cache"):
+        return read_cache(entry)
+
+    return fetch(entry)
+
+
+def recent_failure(entry: dict) -> FetchError:
+    """Check whether the last download of an entry failed less than failureCache minutes ago (1 by default), so a
+    broken remote is not requested again for every calendar served
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the error to report instead of downloading the entry, None if the entry can be downloaded
+    :rtype: FetchError
+    """
+
+    status = read_status(entry)
+    if status.get("error") is None:
+        return None
+
+    seconds = (now() - arrow.get(status["lastRun"])).total_seconds()
+    if seconds >= entry.get("failureCache", 1) * 60:
+        return None
+
+    logger.debug("Not downloading %s again, it failed %d seconds ago", entry["name"], seconds)
+    return FetchError(entry["name"], "the last download failed " + str(int(seconds)) + " seconds ago: "
+                      + status["error"])
+
+
+def mark_stale(cal: Calendar, since: arrow.Arrow) -> Calendar:
+    """Add a new line to the description of all the events of a calendar, mentioning that the source is unavailable
+    and when it was last downloaded
+
+
+    :param cal: calendar to modify
+    :type cal: Calendar
+
+    :param since: the time of the last successful download
+    :type since: arrow.Arrow
+
+
+    :return: the modified calendar
+    :rtype: Calendar
+    """
+
+    mention = 'Source unavailable, last downloaded at ' + since.format("YYYY-MM-DD HH:mm:ss")
+    for event in cal.events:
+        event.description = event.description + '\n' + mention if event.description is not None else mention
+
+    return cal
+
+
+def horodate(cal: Calendar, prefix='', time: arrow.Arrow = None) -> Calendar:
     """Add a new line at the end of the description of every event in the calendar with the current time prefixed by
     the prefix parameter and a space
     The date is added with the following format: YYYY-MM-DD HH:mm:ss
@@ -136,38 +768,458 @@ def horodate(cal: Calendar, prefix='') -> Calendar:
     :param prefix: the prefix to add in front of the date
     :type prefix: str
 
+    :param time: the time to add instead of the current time
+    :type time: arrow.Arrow
+
 
     :return: the modified calendar
     :rtype: Calendar
     """
-    now = arrow.now().format("YYYY-MM-DD HH:mm:ss")
+    current = (time or now()).format("YYYY-MM-DD HH:mm:ss")
     for event in cal.events:
-        event.description = event.description + '\n' + prefix + ' ' + now \
-            if event.description is not None else prefix + ' ' + now
+        event.description = event.description + '\n' + prefix + ' ' + current \
+            if event.description is not None else prefix + ' ' + current
 
     return cal
 
 
-def start_scheduler(scheduler: sched.scheduler) -> None:
-    """Start the caching of every config file found in the app/config directory
+def age(entry: dict) -> float:
+    """Compute the age of the data of an entry: the time since it was cached, or since it was last downloaded
+    successfully if it is not cached
 
 
-    :param scheduler: scheduler object to use to schedule the caching
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the age in seconds, None if the entry has never been cached nor downloaded
+    :rtype: float
+    """
+
+    meta = backend().meta(cache_key(entry, ".ics"))
+    if 'cache' in entry and meta is not None:
+        return now().timestamp - meta["mtime"]
+
+    status = read_status(entry)
+    if "lastSuccess" in status:
+        return (now() - arrow.get(status["lastSuccess"])).total_seconds()
+
+    return None
+
+
+def freshness(entry: dict) -> float:
+    """Compute the remaining freshness of a cached entry: the time until its cached calendar is older than its cache
+    interval
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the remaining freshness in seconds, negative if the cached calendar expired, None if the entry is not
+    cached or has never been cached
+    :rtype: float
+    """
+
+    if not entry.get("cache"):
+        return None
+
+    seconds = age(entry)
+    if seconds is None:
+        return None
+
+    return entry["cache"] * 60 - seconds
+
+
+def refresh(entry: dict) -> bool:
+    """Cache a cached entry again without waiting for its next scheduled download, unless it was cached less than
+    minRefresh minutes ago (1 by default)
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: True if the entry was downloaded again
+    :rtype: bool
+    """
+
+    if not entry.get("cache"):
+        return False
+
+    seconds = age(entry)
+    if seconds is not None and seconds < entry.get("minRefresh", 1) * 60:
+        logger.info("Not refreshing %s, cached %d seconds ago", entry['name'], seconds)
+        return False
+
+    cache(entry)
+    return True
+
+
+def healthcheck() -> Tuple[bool, List[str]]:
+    """Check the freshness and the status of the last download of every entry that is cached or that has a freshness
+    SLA in the config files.
+    A cached entry is stale if it has not been cached for more than twice its caching interval.  An entry with a
+    freshness SLA is stale if its data is older than the SLA
+
+
+    :return: whether every checked entry is healthy, and one line per checked entry describing its health
+    :rtype: Tuple[bool, List[str]]
+    """
+
+    healthy = True
+    lines = []
+
+    for file in config_files():
+        for entry in read_config(file):
+            if 'cache' not in entry and 'freshnessSla' not in entry:
+                continue
+
+            name = file[:-len('.json')] + "/" + entry['name']
+            status = read_status(entry)
+            seconds = age(entry)
+
+            if seconds is None:
+                state, detail = "MISSING", "not cached" if 'cache' in entry else "never downloaded"
+            elif status.get("error") is not None:
+                state, detail = "FAILING", "last run " + arrow.get(status["lastRun"]).humanize() + ": " \
+                                + status["error"]
+            elif 'freshnessSla' in entry and seconds > entry['freshnessSla'] * 60:
+                state, detail = "STALE", "updated " + now().shift(seconds=-seconds).humanize(now()) \
+                                + ", exceeds the freshness SLA of " + str(entry['freshnessSla']) + " minutes"
+            elif 'cache' in entry and seconds > 2 * (entry['cache'] if entry['cache'] > 0 else 10) * 60:
+                state, detail = "STALE", "cached " + now().shift(seconds=-seconds).humanize(now())
+            else:
+                state, detail = "OK", "updated " + now().shift(seconds=-seconds).humanize(now())
+
+            healthy = healthy and state == "OK"
+            lines.append(state + " " + name + ": " + detail)
+
+    return healthy, lines
+
+
+def prune(scheduler: sched.scheduler = None, dry_run: bool = False) -> None:
+    """Remove from the cache the files of the calendars that are no longer in any config file, then the files of the
+    least recently used calendars until the cache fits in its limits.  The calendars that were not used since the
+    start of the application are removed first, the oldest first
+
+
+    :param scheduler: scheduler used to prune the cache again in the future.  If not scheduler is specified, the task
+    will not be relaunched
     :type scheduler: sched.scheduler
+
+    :param dry_run: if true, the files that would be removed are logged but the cache is not modified.  This is
+    always the case once set_dry_run is enabled
+    :type dry_run: bool
+    """
+
+    dry_run = dry_run or _dry_run
+    try:
+        configured = sources()
+
+        groups = calendars()
+        for calendar in [calendar for calendar in groups if calendar not in configured]:
+            logger.info("Removing %s from the cache: no config file uses it anymore", calendar)
+            evict(groups.pop(calendar), dry_run)
+
+        metas = {calendar: [m for m in map(backend().meta, keys) if m is not None]
+                 for calendar, keys in groups.items()}
+        size = sum(m["size"] for calendar in metas for m in metas[calendar])
+
+        def used(calendar: str) -> tuple:
+            return _accessed.get(calendar, 0), max([m["mtime"] for m in metas[calendar]] or [0])
+
+        while groups and (_limits["entries"] is not None and len(groups) > _limits["entries"]
+                          or _limits["size"] is not None and size > _limits["size"] * 1024 * 1024):
+            calendar = min(groups, key=used)
+            logger.info("Removing %s from the cache: the cache is full", calendar)
+            evict(groups.pop(calendar), dry_run)
+            if not dry_run:
+                _evicted.add(calendar)
+            size -= sum(m["size"] for m in metas[calendar])
+
+    except Exception:
+        logger.exception("Could not prune the cache")
+    finally:
+        if scheduler is not None:
+            scheduler.enter(delay=PRUNE_INTERVAL * 60, priority=2, action=prune, argument=(scheduler, dry_run))
+
+
+def evict(keys: List[str], dry_run: bool = False) -> None:
+    """Remove files from the cache
+
+
+    :param keys: the names of the files
+    :type keys: List[str]
+
+    :param dry_run: if true, nothing is removed
+    :type dry_run: bool
+    """
+
+    for key in keys:
+        logger.debug("%s %s", "Would remove" if dry_run else "Removing", key)
+        if not dry_run:
+            backend().delete(key)
+
+
+def calendars() -> dict:
+    """Group the files of the cache by calendar.  The files that are not named like the files of the cache, such as
+    the files of other applications sharing its directory, are left out so they are never removed
+
+
+    :return: the names of the files of every calendar in the cache, by identifier of the calendar
+    :rtype: dict
+    """
+
+    groups = {}
+    for key in backend().keys():
+        if KEY.match(key) is not None:
+            groups.setdefault(key.split(".")[0], []).append(key)
+
+    return groups
+
+
+def sources() -> dict:
+    """
+    :return: the names of the sources of the config files (config file/source), by identifier of their calendar
+    :rtype: dict
+    """
+
+    names = {}
+    for file in config_files():
+        for entry in read_config(file):
+            names.setdefault(cache_key(entry, ""), []).append(file[:-len(".json")] + "/" + entry["name"])
+
+    return names
+
+
+def cache_report() -> List[str]:
+    """Describe the content of the cache: the files of every calendar, their size and their age
+
+
+    :return: the lines describing the calendars in the cache
+    :rtype: List[str]
+    """
+
+    lines = []
+    names = sources()
+
+    for calendar, keys in sorted(calendars().items()):
+        metas = [m for m in map(backend().meta, keys) if m is not None]
+        size = sum(m["size"] for m in metas)
+        updated = arrow.get(max(m["mtime"] for m in metas)).humanize(now()) if metas else "never"
+
+        lines.append(calendar + " (" + (", ".join(names[calendar]) if calendar in names else "not configured")
+                     + "): " + str(len(keys)) + " files, " + str(size) + " bytes, updated " + updated)
+        for key, meta in zip(keys, metas):
+            lines.append("  " + key + ": " + str(meta["size"]) + " bytes")
+
+    return lines
+
+
+def purge(source: str = None) -> int:
+    """Remove the files of a calendar from the cache, or every file
+
+
+    :param source: the identifier of the calendar, or the name of a source using it (config file/source or only the
+    source), None to empty the cache
+    :type source: str
+
+
+    :return: the number of removed files
+    :rtype: int
+    """
+
+    groups = calendars()
+    if source is not None:
+        groups = {calendar: keys for calendar, keys in groups.items() if calendar in matching(source)}
+
+    removed = [key for keys in groups.values() for key in keys]
+    evict(removed)
+    return len(removed)
+
+
+def matching(source: str) -> List[str]:
+    """
+    :param source: the identifier of a calendar, or the name of a source (config file/source or only the source)
+    :type source: str
+
+
+    :return: the identifiers of the calendars designated by the source
+    :rtype: List[str]
+    """
+
+    names = sources()
+    return [calendar for calendar in set(calendars()) | set(names)
+            if calendar == source or any(name == source or name.split("/", 1)[1] == source
+                                         for name in names.get(calendar, []))]
+
+
+def snapshot(entry: dict, data: str) -> None:
+    """Keep a timestamped copy of a newly cached calendar, if the entry keeps snapshots, and remove the snapshots
+    exceeding its retention: more than count snapshots, or older than maxAge days
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+    :param data: the cached calendar
+    :type data: str
+    """
+
+    retention = entry.get("snapshots")
+    if not retention:
+        return
+
+    backend().put(cache_key(entry, ".snapshot." + str(int(now().timestamp)) + ".ics"), data)
+
+    kept = snapshots(cache_key(entry, ""))
+    if "count" in retention:
+        evict(kept[:-retention["count"]] if retention["count"] > 0 else kept)
+        kept = kept[-retention["count"]:] if retention["count"] > 0 else []
+    if "maxAge" in retention:
+        evict([key for key in kept if snapshot_time(key) < now().timestamp - retention["maxAge"] * 24 * 3600])
+
+
+def snapshots(calendar: str) -> List[str]:
+    """
+    :param calendar: the identifier of the calendar
+    :type calendar: str
+
+
+    :return: the names of the snapshots of the calendar in the cache, the oldest first
+    :rtype: List[str]
+    """
+
+    return sorted((key for key in backend().keys() if key.startswith(calendar + ".snapshot.")), key=snapshot_time)
+
+
+def snapshot_time(key: str) -> int:
+    """
+    :param key: the name of a snapshot in the cache
+    :type key: str
+
+
+    :return: the time of the snapshot, as a timestamp
+    :rtype: int
+    """
+
+    return int(key.split(".")[2])
+
+
+def restore(calendar: str, time: int) -> None:
+    """Replace a cached calendar by one of its snapshots.  The snapshot is served until the remote serves a calendar
+    different from the one cached when the snapshot was restored
+
+
+    :param calendar: the identifier of the calendar
+    :type calendar: str
+
+    :param time: the time of the snapshot, as a timestamp
+    :type time: int
+
+
+    :raises KeyError: if there is no such snapshot
+    """
+
+    data = backend().get(calendar + ".snapshot." + str(time) + ".ics")
+    if data is None:
+        raise KeyError("no snapshot of " + calendar + " at " + str(time))
+
+    # The hash of the replaced calendar, kept in the status, makes the next downloads consider the remote unchanged,
+    # while the contentHash of the restored calendar tells the running instances to use it
+    meta = backend().meta(calendar + ".ics") or {}
+    status = json.loads(backend().get(calendar + ".json") or "{}")
+    status["restoredOver"] = status.get("restoredOver") or meta.get("contentHash")
+    backend().put(calendar + ".json", json.dumps(status))
+    backend().put(calendar + ".ics", data, dict(meta, contentHash=sha256(data.encode()).hexdigest()))
+    logger.info("Restored the snapshot of %s taken at %d", calendar, time)
+
+
+def export_cache(path: str) -> int:
+    """Write every file of the cache with its validators to a gzipped JSON lines archive, so it can be imported in the
+    cache of another instance.  The files of an encrypted cache are written decrypted
+
+
+    :param path: the path of the archive
+    :type path: str
+
+
+    :return: the number of exported files
+    :rtype: int
+    """
+
+    count = 0
+    with gzip.open(path, 'wt', encoding='utf-8') as archive:
+        archive.write(json.dumps({"format": ARCHIVE, "version": 1}) + "\n")
+        for key in [key for keys in calendars().values() for key in keys]:
+            data, meta = backend().get(key), backend().meta(key)
+            if data is None or meta is None:
+                continue
+
+            archive.write(json.dumps({"key": key, "data": data, "meta": meta}) + "\n")
+            count += 1
+
+    return count
+
+
+def import_cache(path: str) -> int:
+    """Add the files of an archive written by export_cache to the cache, replacing the files with the same names.
+    The imported files are considered cached at the time of the import
+
+
+    :param path: the path of the archive
+    :type path: str
+
+
+    :return: the number of imported files
+    :rtype: int
+
+
+    :raises ValueError: if the file is not an archive of the cache, or if it contains a file not named like the files of
+    the cache
     """
 
-    path = "app/config"
-    files = [os.path.join(path, f) for f in os.listdir(path)
-             if os.path.isfile(os.path.join(path, f)) and f.endswith('.json')]
+    count = 0
+    with gzip.open(path, 'rt', encoding='utf-8') as archive:
+        header = json.loads(archive.readline() or "{}")
+        if header.get("format") != ARCHIVE or header.get("version") != 1:
+            raise ValueError(path + " is not an archive of the cache")
+
+        for line in archive:
+            item = json.loads(line)
+            # A crafted name, such as ../config/calendar.json, would be written outside of the cache
+            if not isinstance(item.get("key"), str) or KEY.match(item["key"]) is None:
+                raise ValueError(path + " contains a file that is not a file of the cache: " + repr(item.get("key")))
+
+            backend().put(item["key"], item["data"], item["meta"])
+            count += 1
+
+    return count
+
 
-    for file in files:
-        with open(file, 'r') as config_file:
-            config = json.loads(config_file.read())
+def start_scheduler(scheduler: sched.scheduler, dry_run: bool = False) -> None:
+    """Start the caching of every config file found in the app/config directory, and the periodic pruning of the
+    cache
+
+
+    :param scheduler: scheduler object to use to schedule the caching
+    :type scheduler: sched.scheduler
+
+    :param dry_run: if true, the calendars are downloaded but the cache is not written
+    :type dry_run: bool
+    """
 
-        for entry in config:
+    for file in config_files():
+        for entry in read_config(file):
             if 'cache' in entry:
-                scheduler.enter(delay=0, priority=1, action=cache, argument=(entry, scheduler))
+                scheduler.enter(delay=0, priority=1, action=cache, argument=(entry, scheduler, dry_run))
 
+    scheduler.enter(delay=PRUNE_INTERVAL * 60, priority=2, action=prune, argument=(scheduler, dry_run))
     scheduler.run()
 
 
@@ -175,9 +1227,10 @@ class CacheThread(threading.Thread):
     """Child class of the threading.Thread class to run the caching process every 10 minutes
     """
 
-    def __init__(self):
+    def __init__(self, dry_run: bool = False):
         threading.Thread.__init__(self)
+        self.dry_run = dry_run
 
     def run(self):
-        print("Starting cache process")
-        start_scheduler(sched.scheduler(time.time, time.sleep))
+        logger.info("Starting cache process" + (" (dry run)" if self.dry_run else ""))
+        start_scheduler(sched.scheduler(time.time, time.sleep), self.dry_run)
@@ -0,0 +1,71 @@
+"""This module provides the current time to the application.
+
+Every part of the application asking for the current time goes through the now function, so the clock can be
+replaced, e.g. by a fixed clock to test the expiry of the cache or the freshness SLA deterministically:
+
+set_clock(FixedClock(arrow.get("2021-01-01T00:00:00+00:00")))
+"""
+
+import arrow
+
+
+class Clock:
+    """Clock giving the actual current time
+    """
+
+    def now(self) -> arrow.Arrow:
+        """
+        :return: the current time
+        :rtype: arrow.Arrow
+        """
+
+        return arrow.now()
+
+
+class FixedClock(Clock):
+    """Clock always giving the same time, which can be moved forward
+    """
+
+    def __init__(self, time: arrow.Arrow):
+        """
+        :param time: the time given by the clock
+        :type time: arrow.Arrow
+        """
+
+        self.time = time
+
+    def now(self) -> arrow.Arrow:
+        return self.time
+
+    def advance(self, **kwargs) -> None:
+        """Move the clock forward
+
+
+        :param kwargs: the amount of time to move forward, as accepted by arrow.Arrow.shift (minutes=10, ...)
+        """
+
+        self.time = self.time.shift(**kwargs)
+
+
+_clock = Clock()
+
+
+def set_clock(clock: Clock) -> None:
+    """Replace the clock of the application
+
+
+    :param clock: the new clock
+    :type clock: Clock
+    """
+
+    global _clock
+    _clock = clock
+
+
+def now() -> arrow.Arrow:
+    """
+    :return: the current time, according to the clock of the application
+    :rtype: arrow.Arrow
+    """
+
+    return _clock.now()
@@ -0,0 +1,316 @@
+"""This module generates the completion scripts of the command line for bash, zsh and fish.
+
+The scripts are generated from the argparse parser of the application, so they follow its commands and options.  The
+names of the calendars and of the sources are not part of the scripts: they are listed by the application every time
+they are completed, so the scripts stay valid when the config files change.
+"""
+
+import argparse
+import os
+import shlex
+import sys
+from typing import Iterator, List, Tuple
+
+from tools.config import config_files, read_config
+
+SHELLS = ["bash", "zsh", "fish"]
+# The arguments completed with the names of the calendars or of the sources, by destination
+NAMES = {"calendar": "calendars", "source": "sources"}
+
+
+def calendar_names() -> List[str]:
+    """
+    :return: the names of the calendars, i.e. of the config files without the .json extension
+    :rtype: List[str]
+    """
+
+    return [file[:-len(".json")] for file in config_files()]
+
+
+def source_names() -> List[str]:
+    """
+    :return: the names of the sources of every config file, the config files that cannot be read being ignored
+    :rtype: List[str]
+    """
+
+    names = set()
+    for file in config_files():
+        try:
+            names.update(entry["name"] for entry in read_config(file) if "name" in entry)
+        except (OSError, ValueError, TypeError):
+            continue
+
+    return sorted(names)
+
+
+def commands(parser: argparse.ArgumentParser, path: Tuple[str, ...] = ()) \
+        -> Iterator[Tuple[Tuple[str, ...], argparse.ArgumentParser, List[Tuple[str, str]]]]:
+    """Walk the commands of a parser, the parser itself included
+
+
+    :param parser: the parser of the command line
+    :type parser: argparse.ArgumentParser
+
+    :param path: the names of the commands leading to the parser
+    :type path: Tuple[str, ...]
+
+
+    :return: for every command, its path, its parser and the names and help of its subcommands
+    :rtype: Iterator[Tuple[Tuple[str, ...], argparse.ArgumentParser, List[Tuple[str, str]]]]
+    """
+
+    children = []
+    for action in parser._actions:
+        if isinstance(action, argparse._SubParsersAction):
+            helps = {choice.dest: choice.help or "" for choice in action._choices_actions}
+            children += [(name, helps.get(name, ""), sub) for name, sub in action.choices.items()]
+
+    yield path, parser, [(name, text) for name, text, _ in children]
+    for name, _, sub in children:
+        yield from commands(sub, path + (name,))
+
+
+def options(parser: argparse.ArgumentParser) -> Iterator[argparse.Action]:
+    """
+    :param parser: the parser of a command
+    :type parser: argparse.ArgumentParser
+
+
+    :return: the options of the command, the hidden ones excepted
+    :rtype: Iterator[argparse.Action]
+    """
+
+    for action in parser._actions:
+        if action.option_strings and action.help != argparse.SUPPRESS:
+            yield action
+
+
+def positionals(parser: argparse.ArgumentParser) -> Tuple[str, List[str]]:
+    """
+    :param parser: the parser of a command
+    :type parser: argparse.ArgumentParser
+
+
+    :return: how the positional arguments of the command are completed: ("names", [kind]) for the names of the
+    calendars or of the sources, ("choices", choices) for a fixed list of values and ("none", []) otherwise
+    :rtype: Tuple[str, List[str]]
+    """
+
+    for action in parser._actions:
+        if action.option_strings or isinstance(action, argparse._SubParsersAction):
+            continue
+        if action.dest in NAMES:
+            return "names", [NAMES[action.dest]]
+        if action.choices is not None:
+            return "choices", [str(choice) for choice in action.choices]
+
+    return "none", []
+
+
+def values(action: argparse.Action) -> Tuple[str, List[str]]:
+    """
+    :param action: an option of a command
+    :type action: argparse.Action
+
+
+    :return: how the value of the option is completed: ("none", []) for a flag, ("names", [kind]) for the names of
+    the calendars or of the sources, ("choices", choices) for a fixed list of values and ("files", []) otherwise
+    :rtype: Tuple[str, List[str]]
+    """
+
+    if action.nargs == 0:
+        return "none", []
+    if action.dest in NAMES:
+        return "names", [NAMES[action.dest]]
+    if action.choices is not None:
+        return "choices", [str(choice) for choice in action.choices]
+
+    return "files", []
+
+
+def lister() -> str:
+    """
+    :return: the shell command listing the names of the calendars or of the sources given as first argument.  It runs
+    the application from the current directory, where the app/config directory is looked for
+    :rtype: str
+    """
+
+    return "cd " + shlex.quote(os.getcwd()) + " && " + shlex.quote(sys.executable) + " " \
+           + shlex.quote(os.path.abspath(sys.argv[0])) + " completions --names"
+
+
+def bash(parser: argparse.ArgumentParser, command: str) -> str:
+    """Generate the bash completion script of the command line
+
+
+    :param parser: the parser of the command line
+    :type parser: argparse.ArgumentParser
+
+    :param command: the name of the command to complete
+    :type command: str
+
+
+    :return: the script
+    :rtype: str
+    """
+
+    function = "_" + "".join(c if c.isalnum() else "_" for c in command)
+    descents, prefixes, words = [], [], []
+    for path, sub, children in commands(parser):
+        key = " ".join(path)
+        for name, _ in children:
+            descents.append('            "' + key + "|" + name + '") path="' + " ".join(path + (name,)) + '" ;;')
+
+        positional, items = positionals(sub)
+        for action in options(sub):
+            kind, choices = values(action)
+            pattern = "|".join('"' + key + "|" + option + '"' for option in action.option_strings)
+            if kind == "names":
+                prefixes.append("        " + pattern + ') names="' + choices[0] + '" ;;')
+            elif kind == "choices":
+                prefixes.append("        " + pattern + ') COMPREPLY=($(compgen -W "' + " ".join(choices)
+                                + '" -- "$cur")); return ;;')
+            elif kind == "files":
+                prefixes.append("        " + pattern + ') COMPREPLY=($(compgen -f -- "$cur")); return ;;')
+
+        offered = [option for action in options(sub) for option in action.option_strings] \
+            + [name for name, _ in children] + (items if positional == "choices" else [])
+        names = items[0] if positional == "names" else ""
+        words.append('                "' + key + '") words="' + " ".join(offered) + '"; names="' + names + '" ;;')
+
+    return "\n".join([
+        function + "_names() {",
+        "    (" + lister() + ' "$1" 2>/dev/null)',
+        "}",
+        "",
+        function + "() {",
+        '    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}" path="" words="" names="" i',
+        "    for ((i = 1; i < COMP_CWORD; i++)); do",
+        '        case "$path|${COMP_WORDS[i]}" in',
+    ] + descents + [
+        "        esac",
+        "    done",
+        '    case "$path|$prev" in',
+    ] + prefixes + [
+        "        *)",
+        '            case "$path" in',
+    ] + words + [
+        "            esac ;;",
+        "    esac",
+        '    COMPREPLY=($(compgen -W "$words" -- "$cur"))',
+        '    if [[ -n "$names" && "$cur" != -* ]]; then',
+        "        # The names may contain spaces, they are listed one per line and escaped",
+        "        local IFS=$'\\n' name",
+        '        for name in $(compgen -W "$(' + function + '_names "$names")" -- "$cur"); do',
+        """            COMPREPLY+=("$(printf '%q' "$name")")""",
+        "        done",
+        "    fi",
+        "}",
+        "",
+        "complete -o default -F " + function + " " + shlex.quote(command),
+        "",
+    ])
+
+
+def zsh(parser: argparse.ArgumentParser, command: str) -> str:
+    """Generate the zsh completion script of the command line, which relies on the bash completion of zsh
+
+
+    :param parser: the parser of the command line
+    :type parser: argparse.ArgumentParser
+
+    :param command: the name of the command to complete
+    :type command: str
+
+
+    :return: the script
+    :rtype: str
+    """
+
+    return "autoload -U +X bashcompinit && bashcompinit\n\n" + bash(parser, command)
+
+
+def fish(parser: argparse.ArgumentParser, command: str) -> str:
+    """Generate the fish completion script of the command line
+
+
+    :param parser: the parser of the command line
+    :type parser: argparse.ArgumentParser
+
+    :param command: the name of the command to complete
+    :type command: str
+
+
+    :return: the script
+    :rtype: str
+    """
+
+    function = "__" + "".join(c if c.isalnum() else "_" for c in command)
+    everything = [name for _, _, children in commands(parser) for name, _ in children]
+    lines = [
+        "function " + function + "_names",
+        "    sh -c " + shlex.quote(lister() + ' "$1" 2>/dev/null') + " sh $argv[1]",
+        "end",
+        "",
+    ]
+
+    for path, sub, children in commands(parser):
+        if path:
+            condition = "; and ".join("__fish_seen_subcommand_from " + name for name in path)
+        else:
+            condition = "not __fish_seen_subcommand_from " + " ".join(everything) if everything else "true"
+        if children:
+            inner = "not __fish_seen_subcommand_from " + " ".join(name for name, _ in children)
+            complete = "complete -c " + shlex.quote(command) + " -f -n " + shlex.quote(
+                condition + "; and " + inner if path else condition)
+            for name, text in children:
+                lines.append(complete + " -a " + shlex.quote(name) + " -d " + shlex.quote(text))
+
+        start = "complete -c " + shlex.quote(command) + " -n " + shlex.quote(condition)
+        for action in options(sub):
+            kind, choices = values(action)
+            flags = "".join(" -l " + option[2:] if option.startswith("--") else " -s " + option[1:]
+                            for option in action.option_strings)
+            if kind == "names":
+                flags += " -x -a " + shlex.quote("(" + function + "_names " + choices[0] + ")")
+            elif kind == "choices":
+                flags += " -x -a " + shlex.quote(" ".join(choices))
+            elif kind == "files":
+                flags += " -r"
+            lines.append(start + flags + " -d " + shlex.quote(action.help or ""))
+
+        positional, items = positionals(sub)
+        if positional == "names":
+            lines.append(start + " -f -a " + shlex.quote("(" + function + "_names " + items[0] + ")"))
+        elif positional == "choices":
+            lines.append(start + " -f -a " + shlex.quote(" ".join(items)))
+
+    return "\n".join(lines) + "\n"
+
+
+def script(parser: argparse.ArgumentParser, shell: str, command: str = None) -> str:
+    """Generate the completion script of the command line for a shell
+
+
+    :param parser: the parser of the command line
+    :type parser: argparse.ArgumentParser
+
+    :param shell: the shell, one of SHELLS
+    :type shell: str
+
+    :param command: the name of the command to complete, the name of the program by default
+    :type command: str
+
+
+    :return: the script
+    :rtype: str
+
+
+    :raises ValueError: if the shell is not supported
+    """
+
+    generators = {"bash": bash, "zsh": zsh, "fish": fish}
+    if shell not in generators:
+        raise ValueError("unsupported shell " + shell)
+
+    return generators[shell](parser, command or parser.prog)
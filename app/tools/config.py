@@ -0,0 +1,95 @@
+"""This module provides methods to find and read the JSON configuration files of the app/config directory.
+
+A configuration file is either the list of the entries of the calendar, or an object with the entries in its sources
+field and the options of the merged calendar in its calendar field.
+"""
+
+import json
+import logging
+import os
+from typing import List
+
+from pathvalidate import sanitize_filename
+
+logger = logging.getLogger(__name__)
+
+CONFIG_DIR = "app/config"
+# The names of the endpoints of the application, which would hide the calendars with the same name
+RESERVED = ("metrics.json", "health.json")
+
+
+def config_files() -> List[str]:
+    """List the configuration files found in the app/config directory.  The files named after an endpoint of the
+    application, such as metrics.json, are ignored since their calendar could not be served
+
+
+    :return: the names of the configuration files, including the .json extension
+    :rtype: List[str]
+    """
+
+    files = sorted(f for f in os.listdir(CONFIG_DIR)
+                   if os.path.isfile(os.path.join(CONFIG_DIR, f)) and f.endswith('.json'))
+
+    for f in files:
+        if f in RESERVED:
+            logger.warning("Ignoring %s: the %s endpoint is reserved by the application", f, f[:-len('.json')])
+
+    return [f for f in files if f not in RESERVED]
+
+
+def load(name: str):
+    """Read the content of a configuration file from the app/config directory
+
+
+    :param name: name of the file to open, including the .json extension
+    :type name: str
+
+
+    :return: the content of the configuration file
+    :rtype: Union[list, dict]
+
+
+    :raises FileNotFoundError: if the configuration file does not exist
+    """
+
+    path = os.path.join(CONFIG_DIR, sanitize_filename(name))
+    with open(path, 'r') as file:
+        return json.loads(file.read())
+
+
+def read_config(name: str) -> List[dict]:
+    """Read a configuration file from the app/config directory
+
+
+    :param name: name of the file to open, including the .json extension
+    :type name: str
+
+
+    :return: the entries listed in the configuration file
+    :rtype: List[dict]
+
+
+    :raises FileNotFoundError: if the configuration file does not exist
+    """
+
+    config = load(name)
+    return config.get("sources", []) if isinstance(config, dict) else config
+
+
+def read_options(name: str) -> dict:
+    """Read the options of the merged calendar from a configuration file of the app/config directory
+
+
+    :param name: name of the file to open, including the .json extension
+    :type name: str
+
+
+    :return: the options of the merged calendar, empty if the configuration file is only the list of the entries
+    :rtype: dict
+
+
+    :raises FileNotFoundError: if the configuration file does not exist
+    """
+
+    config = load(name)
+    return config.get("calendar", {}) if isinstance(config, dict) else {}
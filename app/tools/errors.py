@@ -0,0 +1,79 @@
+"""This module defines the errors raised when a calendar cannot be retrieved, so the callers can tell a network
+failure from an HTTP error, a calendar that cannot be parsed or a calendar missing from the cache.
+"""
+
+
+class FusionError(Exception):
+    """Base class of the errors of the application.  The message names the source concerned
+    """
+
+    def __init__(self, source: str, message: str):
+        """
+        :param source: name of the source concerned
+        :type source: str
+
+        :param message: description of the error
+        :type message: str
+        """
+
+        Exception.__init__(self, source + ": " + message)
+        self.source = source
+
+
+class FetchError(FusionError):
+    """The calendar could not be retrieved from the remote: network failure, timeout, unreadable file...
+    """
+
+
+class HttpError(FetchError):
+    """The remote answered with an HTTP error status
+    """
+
+    def __init__(self, source: str, status: int):
+        """
+        :param source: name of the source concerned
+        :type source: str
+
+        :param status: the HTTP status of the response
+        :type status: int
+        """
+
+        FetchError.__init__(self, source, "HTTP " + str(status))
+        self.status = status
+
+
+class RateLimitedError(HttpError):
+    """The remote asked not to be requested again before some time, with the Retry-After header
+    """
+
+    def __init__(self, source: str, status: int, until):
+        """
+        :param source: name of the source concerned
+        :type source: str
+
+        :param status: the HTTP status of the response, 429 or 503
+        :type status: int
+
+        :param until: the time before which the remote must not be requested again
+        :type until: arrow.Arrow
+        """
+
+        FetchError.__init__(self, source, "HTTP " + str(status) + ", retry after " + until.isoformat())
+        self.status = status
+        self.until = until
+
+
+class ParseError(FusionError):
+    """The content served by the remote could not be decoded or parsed as a calendar
+    """
+
+
+class NotModified(Exception):
+    """The remote answered that the calendar did not change since the previous download.  This is not an error: the
+    previous copy of the calendar is still valid
+    """
+
+
+class NotCachedError(FusionError, FileNotFoundError):
+    """The calendar was supposed to be cached but has not been cached yet
+    """
@@ -0,0 +1,92 @@
+"""This module lets the applications embedding ICS Fusion react to its lifecycle events without modifying it.
+
+The callbacks are registered with the on_* functions, which can also be used as decorators:
+
+@on_fetch_failure
+def notify(entry, error):
+    ...
+
+- on_fetch_success: called with the entry and the metrics of the download when a calendar is downloaded
+- on_fetch_failure: called with the entry and the description of the error when a download fails
+- on_feed_generated: called with the name of the config file, the merged calendar and the difference with the
+previous generation of the same calendar, as a dict with the "added" and "removed" UIDs
+
+An exception raised by a callback is logged and does not interrupt the application.
+"""
+
+import logging
+from typing import Callable
+
+logger = logging.getLogger(__name__)
+
+_callbacks = {
+    "fetch_success": [],
+    "fetch_failure": [],
+    "feed_generated": [],
+}
+
+
+def on_fetch_success(callback: Callable) -> Callable:
+    """Register a callback called when a calendar is downloaded successfully
+
+
+    :param callback: function taking the entry and the metrics of the download
+    :type callback: Callable
+
+
+    :return: the callback
+    :rtype: Callable
+    """
+
+    _callbacks["fetch_success"].append(callback)
+    return callback
+
+
+def on_fetch_failure(callback: Callable) -> Callable:
+    """Register a callback called when the download of a calendar fails
+
+
+    :param callback: function taking the entry and the description of the error
+    :type callback: Callable
+
+
+    :return: the callback
+    :rtype: Callable
+    """
+
+    _callbacks["fetch_failure"].append(callback)
+    return callback
+
+
+def on_feed_generated(callback: Callable) -> Callable:
+    """Register a callback called when a merged calendar is generated
+
+
+    :param callback: function taking the name of the config file, the calendar and the difference with the previous
+    generation
+    :type callback: Callable
+
+
+    :return: the callback
+    :rtype: Callable
+    """
+
+    _callbacks["feed_generated"].append(callback)
+    return callback
+
+
+def dispatch(event: str, *args) -> None:
+    """Call the callbacks registered for a lifecycle event
+
+
+    :param event: the lifecycle event: fetch_success, fetch_failure or feed_generated
+    :type event: str
+
+    :param args: the arguments given to the callbacks
+    """
+
+    for callback in _callbacks[event]:
+        try:
+            callback(*args)
+        except Exception:
+            logger.exception("The %s callback %s failed", event, callback)
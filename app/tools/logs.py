@@ -0,0 +1,177 @@
+"""This module configures the logging of the application.
+
+The logs can be written as text or as JSON, one object per line.  The level can be set globally and overridden per
+module, e.g. "info,tools.caching=debug".
+
+The URLs found in the logs are redacted: the credentials, the values of the query parameters that look like secrets
+and the path segments that look like tokens are replaced by "***", since private calendar links usually embed a
+secret token.
+
+The spans group the logs of one operation (fetching a remote, caching an entry, merging a calendar, serving a
+request).  Every log emitted inside a span carries the name and the identifier of the span and of its parents, so
+the logs of one operation can be correlated even when several operations run at the same time.
+"""
+
+import json
+import logging
+import re
+import threading
+import time
+import uuid
+from contextlib import contextmanager
+from typing import List
+from urllib.parse import urlsplit, urlunsplit
+
+_local = threading.local()
+
+URL = re.compile(r"[a-zA-Z][a-zA-Z0-9+.-]*://[^\s'\"<>]+")
+SECRET_PARAMETER = re.compile(r"([?&]|^)([^?&=\s]*(?:token|key|secret|pass|auth|sig|code|session)[^&=\s]*=)"
+                              r"[^&\s]*", re.IGNORECASE)
+# The path segments with the shape of a token: the private addresses of Google Calendar, the hexadecimal tokens, the
+# UUIDs (followed by the domain in Outlook) and the long tokens mixing digits, lower and upper case letters.  The file
+# names, such as calendar2024.ics, do not match
+SECRET_SEGMENT = re.compile(r"^private-.+$"
+                            r"|^[0-9a-fA-F]{32,}$"
+                            r"|^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}"
+                            r"|^(?=.*[0-9])(?=.*[a-z])(?=.*[A-Z])[a-zA-Z0-9_-]{16,}$")
+
+
+def redact_url(url: str) -> str:
+    """Hide the secrets of a URL: the credentials, the values of the query parameters that look like secrets and the
+    path segments that look like tokens
+
+
+    :param url: the URL to redact
+    :type url: str
+
+
+    :return: the redacted URL
+    :rtype: str
+    """
+
+    parts = urlsplit(url)
+
+    netloc = parts.netloc
+    if "@" in netloc:
+        netloc = "***@" + netloc.rpartition("@")[2]
+
+    path = "/".join("***" if SECRET_SEGMENT.match(segment) else segment for segment in parts.path.split("/"))
+    query = SECRET_PARAMETER.sub(r"\1\2***", parts.query)
+
+    return urlunsplit((parts.scheme, netloc, path, query, parts.fragment))
+
+
+def redact(text: str) -> str:
+    """Hide the secrets of every URL found in a text.  The query parameters that look like secrets are hidden even
+    when the URL is incomplete, as in the error messages of the HTTP client
+
+
+    :param text: the text to redact
+    :type text: str
+
+
+    :return: the redacted text
+    :rtype: str
+    """
+
+    text = URL.sub(lambda match: redact_url(match.group(0)), text)
+    return re.sub(r"\?\S+", lambda match: SECRET_PARAMETER.sub(r"\1\2***", match.group(0)), text)
+
+
+class SpanFilter(logging.Filter):
+    """Filter adding the spans of the current thread to the log records
+    """
+
+    def filter(self, record: logging.LogRecord) -> bool:
+        spans = getattr(_local, "spans", [])
+        record.spans = [dict(span) for span in spans]
+        record.span = "/".join(span["span"] + ":" + span["id"] for span in spans)
+        return True
+
+
+class TextFormatter(logging.Formatter):
+    """Formatter writing the log records as redacted text
+    """
+
+    def format(self, record: logging.LogRecord) -> str:
+        return redact(super().format(record))
+
+
+class JsonFormatter(logging.Formatter):
+    """Formatter writing the log records as redacted JSON objects
+    """
+
+    def format(self, record: logging.LogRecord) -> str:
+        data = {"time": self.formatTime(record), "level": record.levelname, "logger": record.name,
+                "message": record.getMessage(), "spans": getattr(record, "spans", [])}
+        if record.exc_info:
+            data["exception"] = self.formatException(record.exc_info)
+
+        return redact(json.dumps(data))
+
+
+@contextmanager
+def span(name: str, **fields):
+    """Open a span around an operation.  The end of the span is logged with its duration
+
+
+    :param name: name of the operation
+    :type name: str
+
+    :param fields: values describing the operation, added to the logs emitted inside the span
+    """
+
+    if not hasattr(_local, "spans"):
+        _local.spans = []
+
+    current = dict(fields, span=name, id=uuid.uuid4().hex[:8])
+    _local.spans.append(current)
+    start = time.monotonic()
+
+    try:
+        yield current
+    finally:
+        logging.getLogger("tools.logs").debug("%s done in %d ms", name, (time.monotonic() - start) * 1000)
+        _local.spans.pop()
+
+
+def setup_logging(json_output: bool = False, levels: str = "info") -> None:
+    """Configure the logging of the application
+
+
+    :param json_output: if true, the logs are written as JSON objects, one per line
+    :type json_output: bool
+
+    :param levels: comma separated levels, either global ("info") or for a module ("tools.caching=debug")
+    :type levels: str
+    """
+
+    handler = logging.StreamHandler()
+    handler.addFilter(SpanFilter())
+    if json_output:
+        handler.setFormatter(JsonFormatter())
+    else:
+        handler.setFormatter(TextFormatter("%(asctime)s %(levelname)s %(name)s [%(span)s] %(message)s"))
+
+    root = logging.getLogger()
+    root.handlers = [handler]
+
+    for level in parse_levels(levels):
+        module, _, value = level.rpartition("=")
+        logging.getLogger(module or None).setLevel(value.upper())
+
+
+def parse_levels(levels: str) -> List[str]:
+    """Split the comma separated levels given to setup_logging
+
+
+    :param levels: comma separated levels
+    :type levels: str
+
+
+    :return: the levels, the global one first
+    :rtype: List[str]
+    """
+
+    items = [level.strip() for level in levels.split(",") if level.strip()]
+    return sorted(items, key=lambda level: "=" in level)
@@ -0,0 +1,128 @@
+"""This module exposes the metrics measured when downloading the calendars in the Prometheus text format."""
+
+from typing import List
+
+from tools.caching import age, read_status
+from tools.config import config_files, read_config
+
+METRICS = [
+    ("fetchDuration", "ics_fusion_fetch_duration_seconds", "Duration of the last request to the remote"),
+    ("payloadSize", "ics_fusion_payload_size_bytes", "Size of the last calendar served by the remote"),
+    ("parseDuration", "ics_fusion_parse_duration_seconds", "Duration of the parsing of the last calendar"),
+    ("events", "ics_fusion_events", "Number of events in the last calendar served by the remote"),
+    ("recurring", "ics_fusion_recurring_events", "Number of recurring events in the last calendar"),
+    ("delta", "ics_fusion_events_delta", "Difference of the number of events with the previous download"),
+]
+
+EXTRA = [
+    ("ics_fusion_consecutive_failures", "Number of consecutive failed downloads"),
+    ("ics_fusion_age_seconds", "Time since the data of the source was last updated"),
+    ("ics_fusion_freshness_sla_exceeded", "Whether the data of the source is older than its freshness SLA"),
+]
+
+
+def label(value: str) -> str:
+    """Escape a value to use it as a Prometheus label
+
+
+    :param value: the value to escape
+    :type value: str
+
+
+    :return: the escaped value
+    :rtype: str
+    """
+
+    return value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+
+
+def prometheus() -> str:
+    """Build the metrics of every source of every config file
+
+
+    :return: the metrics in the Prometheus text format
+    :rtype: str
+    """
+
+    samples = {name: [] for _, name, _ in METRICS}
+    for name, _ in EXTRA:
+        samples[name] = []
+
+    for file in config_files():
+        for entry in read_config(file):
+            status = read_status(entry)
+            labels = "{calendar=\"" + label(file[:-len(".json")]) + "\",source=\"" + label(entry["name"]) + "\"}"
+
+            for key, name, _ in METRICS:
+                if key in status.get("metrics", {}):
+                    samples[name].append(name + labels + " " + str(status["metrics"][key]))
+
+            if "consecutiveFailures" in status:
+                samples["ics_fusion_consecutive_failures"].append("ics_fusion_consecutive_failures" + labels + " "
+                                                                  + str(status["consecutiveFailures"]))
+
+            seconds = age(entry)
+            if seconds is not None:
+                samples["ics_fusion_age_seconds"].append("ics_fusion_age_seconds" + labels + " " + str(seconds))
+                if "freshnessSla" in entry:
+                    stale = 1 if seconds > entry["freshnessSla"] * 60 else 0
+                    samples["ics_fusion_freshness_sla_exceeded"].append("ics_fusion_freshness_sla_exceeded" + labels
+                                                                        + " " + str(stale))
+
+    lines = []
+    descriptions = [(name, description) for _, name, description in METRICS]
+    descriptions.extend(EXTRA)
+
+    for name, description in descriptions:
+        lines.append("# HELP " + name + " " + description)
+        lines.append("# TYPE " + name + " gauge")
+        lines.extend(samples[name])
+
+    return "\n".join(lines) + "\n"
+
+
+def status_report() -> List[str]:
+    """Describe the last download of every source of every config file
+
+
+    :return: the lines describing the sources
+    :rtype: List[str]
+    """
+
+    lines = []
+
+    for file in config_files():
+        for entry in read_config(file):
+            status = read_status(entry)
+            name = file[:-len(".json")] + "/" + entry["name"]
+
+            if not status:
+                lines.append(name + ": never downloaded")
+                continue
+
+            metrics = status.get("metrics", {})
+            lines.append(name + ": " + ("failed" if status["error"] is not None else "ok") + " at "
+                         + status["lastRun"] + ", " + str(status.get("consecutiveFailures", 0))
+                         + " consecutive failures")
+            if status["error"] is not None:
+                lines.append("  error: " + status["error"])
+            if "fetchDuration" in metrics:
+                lines.append("  fetched " + str(metrics["payloadSize"]) + " bytes in "
+                             + "{:.2f}".format(metrics["fetchDuration"]) + " s")
+            if "parseDuration" in metrics:
+                lines.append("  parsed " + str(metrics["events"]) + " events in "
+                             + "{:.2f}".format(metrics["parseDuration"]) + " s")
+            if "recurring" in metrics:
+                lines.append("  " + str(metrics["recurring"]) + " recurring and "
+                             + str(metrics["events"] - metrics["recurring"]) + " single events")
+            if "firstEvent" in metrics:
+                lines.append("  covering " + metrics["firstEvent"] + " to " + metrics["lastEvent"])
+            if "delta" in metrics:
+                lines.append("  " + "{:+d}".format(metrics["delta"]) + " events since the previous download")
+            if "changes" in metrics:
+                lines.append("  last changed at " + metrics["changes"]["time"] + ":")
+                for change in ("added", "removed", "modified"):
+                    if metrics["changes"][change]:
+                        lines.append("    " + change + ": " + ", ".join(metrics["changes"][change]))
+
+    return lines
@@ -0,0 +1,215 @@
+"""This module parses the content lines of the calendars while preserving their parameters.
+
+The properties unknown to the parser, such as the vendor extensions X-MICROSOFT-CDO-BUSYSTATUS or
+X-APPLE-STRUCTURED-LOCATION, are kept on the events with their parameters and written as is in the merged calendar.
+The TatSu grammar of ics removes the quotes around the parameter values, and ContentLine writes the values back
+without them.  A quoted value containing a colon, a semicolon or a comma, such as SENT-BY="mailto:jane@example.com"
+or CN="Doe, John", is then written as an unquoted value and split again by the next reader of the merged calendar.
+The calendars are therefore parsed here: the parameter values are unquoted like ics does, so the parsers of ics read
+them as usual, and the quotes are written back by QuotedLine.  The global parser of ics is left unchanged.
+"""
+
+import re
+from typing import Iterable, Iterator, List, Union
+
+from ics import Calendar
+from ics.grammar.parse import Container, ContentLine, ParseError
+
+from tools.recurrence import override
+
+LINE = re.compile(r'^([^;:]+)((?:;[^=;:]+=(?:"[^"]*"|[^";:,]*)(?:,(?:"[^"]*"|[^";:,]*))*)*):(.*)$', re.DOTALL)
+PARAMETER = re.compile(r';([^=;:]+)=((?:"[^"]*"|[^";:,]*)(?:,(?:"[^"]*"|[^";:,]*))*)')
+VALUE = re.compile(r'(?:^|,)("[^"]*"|[^",]*)')
+# The characters that cannot appear in an unquoted parameter value
+SPECIAL = re.compile(r'[:;,]')
+
+
+class QuotedLine(ContentLine):
+    """A content line whose parameter values are written back with their quotes.  A value is quoted if it was quoted
+    in the parsed calendar or if it contains a colon, a semicolon or a comma
+    """
+
+    def __init__(self, name: str, params: dict = None, value: str = "", quoted: Iterable = ()):
+        super().__init__(name, params, value)
+        self.quoted = set(quoted)
+
+    def __str__(self):
+        params = ""
+        for name, values in self.params.items():
+            quoted = ['"' + v + '"' if (name, v) in self.quoted or SPECIAL.search(v) else v for v in values]
+            params += ";" + name + "=" + ",".join(quoted)
+
+        return self.name + params + ":" + self.value
+
+    def clone(self):
+        return clone(self)
+
+
+def clone(line: ContentLine) -> ContentLine:
+    """Copy a content line, keeping the quotes of its parameter values
+
+
+    :param line: the content line to copy
+    :type line: ContentLine
+
+
+    :return: the copy
+    :rtype: ContentLine
+    """
+
+    params = {name: list(values) for name, values in line.params.items()}
+    if isinstance(line, QuotedLine):
+        return QuotedLine(line.name, params, line.value, line.quoted)
+
+    return ContentLine(name=line.name, params=params, value=line.value)
+
+
+def parse_line(line: str) -> ContentLine:
+    """Parse a content line, the quoted parameter values being allowed to contain colons, semicolons and commas.
+    The value of the property is kept as is.  The lines that do not follow the format are handed to the parser of ics
+
+
+    :param line: the unfolded content line
+    :type line: str
+
+
+    :return: the parsed content line
+    :rtype: ContentLine
+
+
+    :raises ParseError: if the line cannot be parsed
+    """
+
+    match = LINE.match(line)
+    if match is None:
+        return ContentLine.parse(line)
+
+    params = {}
+    quoted = set()
+    for parameter in PARAMETER.finditer(match.group(2)):
+        name = parameter.group(1)
+        params[name] = []
+        for value in VALUE.findall(parameter.group(2)):
+            if len(value) >= 2 and value.startswith('"') and value.endswith('"'):
+                value = value[1:-1]
+                quoted.add((name, value))
+            params[name].append(value)
+
+    return QuotedLine(match.group(1), params, match.group(3), quoted)
+
+
+def unfold(lines: Iterable[str]) -> Iterator[str]:
+    """Join the folded content lines, the continuation lines starting with a space or a tab
+
+
+    :param lines: the physical lines of the calendar
+    :type lines: Iterable[str]
+
+
+    :return: the unfolded content lines
+    :rtype: Iterator[str]
+    """
+
+    current = None
+    for line in lines:
+        if not line.strip():
+            continue
+        if current is not None and line[0] in (" ", "\t"):
+            current += line[1:]
+        else:
+            if current is not None:
+                yield current
+            current = line
+
+    if current is not None:
+        yield current
+
+
+def nest(name: str, lines: Iterator[ContentLine]) -> Container:
+    """Gather the content lines of a component up to its END line, the nested components included
+
+
+    :param name: the name of the component
+    :type name: str
+
+    :param lines: the content lines following the BEGIN line of the component
+    :type lines: Iterator[ContentLine]
+
+
+    :return: the component
+    :rtype: Container
+
+
+    :raises ParseError: if the component is not closed by the matching END line
+    """
+
+    items = []
+    for line in lines:
+        if line.name == "BEGIN":
+            items.append(nest(line.value, lines))
+        elif line.name == "END":
+            if line.value.upper() != name.upper():
+                raise ParseError("expected END:" + name + ", got END:" + line.value)
+            return Container(name, *items)
+        else:
+            items.append(line)
+
+    raise ParseError("missing END:" + name)
+
+
+def string_to_container(data: str) -> List[Union[Container, ContentLine]]:
+    """Parse a calendar, or a component of a calendar, into the Containers and ContentLines of ics
+
+
+    :param data: the text to parse
+    :type data: str
+
+
+    :return: the top-level components and content lines
+    :rtype: List[Union[Container, ContentLine]]
+
+
+    :raises ParseError: if the text cannot be parsed
+    """
+
+    lines = (parse_line(line) for line in unfold(data.splitlines()))
+    return [nest(line.value, lines) if line.name == "BEGIN" else line for line in lines]
+
+
+def parse_calendar(data: str) -> Calendar:
+    """Parse a calendar, keeping the quotes of its parameter values.  The events overriding an occurrence of a
+    recurring event, which ics would confuse with the recurring event since they share its UID, are parsed one at a
+    time and added to the events of the calendar as Overrides
+
+
+    :param data: the content of the calendar
+    :type data: str
+
+
+    :return: the parsed calendar
+    :rtype: Calendar
+
+
+    :raises ParseError: if the text cannot be parsed
+    :raises NotImplementedError: if the text contains several calendars
+    """
+
+    containers = [item for item in string_to_container(data) if isinstance(item, Container)]
+    if len(containers) != 1:
+        raise NotImplementedError("expected a single calendar, found " + str(len(containers)))
+
+    def overriding(item) -> bool:
+        return isinstance(item, Container) and item.name.upper() == "VEVENT" \
+            and any(line.name == "RECURRENCE-ID" for line in item if isinstance(line, ContentLine))
+
+    calendar = containers[0]
+    cal = Calendar(imports=Container(calendar.name, *[item for item in calendar if not overriding(item)]))
+
+    # The properties and the timezones of the calendar are needed to parse the dates of an override
+    context = [item for item in calendar if not isinstance(item, Container) or item.name.upper() == "VTIMEZONE"]
+    for item in calendar:
+        if overriding(item):
+            for event in Calendar(imports=Container(calendar.name, *(context + [item]))).events:
+                cal.events.add(override(event))
+
+    return cal
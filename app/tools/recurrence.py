@@ -0,0 +1,222 @@
+"""This module computes the occurrences of the events, taking their recurrence into account.
+
+The ics package does not interpret the recurrence of the events: the RRULE, RDATE, EXDATE and RECURRENCE-ID
+properties are kept as is in the extra properties of the events.  They are interpreted here with dateutil.
+"""
+
+import logging
+import re
+from datetime import datetime, timedelta, tzinfo
+from typing import Iterable, List, Tuple
+
+import arrow
+from dateutil import tz
+from dateutil.rrule import rruleset, rrulestr
+from ics import Event
+from ics.grammar.parse import ContentLine
+
+from tools.timezones import resolve
+
+logger = logging.getLogger(__name__)
+
+UNTIL = re.compile(r"(?:^|;)UNTIL=([0-9TZ]+)", re.IGNORECASE)
+
+
+def parse_dates(line: ContentLine, default: tzinfo) -> List[datetime]:
+    """Parse the dates of a RDATE, EXDATE or RECURRENCE-ID property
+
+
+    :param line: the property to parse
+    :type line: ContentLine
+
+    :param default: the timezone of the floating dates
+    :type default: tzinfo
+
+
+    :return: the dates listed in the property
+    :rtype: List[datetime]
+    """
+
+    tzid = line.params.get("TZID", [None])[0]
+    zone = (resolve(tzid) or default) if tzid else default
+    dates = []
+
+    for value in line.value.split(","):
+        value = value.strip().split("/")[0]
+        if value.endswith("Z"):
+            dates.append(datetime.strptime(value, "%Y%m%dT%H%M%SZ").replace(tzinfo=tz.tzutc()))
+        elif "T" in value:
+            dates.append(datetime.strptime(value, "%Y%m%dT%H%M%S").replace(tzinfo=zone))
+        else:
+            dates.append(datetime.strptime(value, "%Y%m%d").replace(tzinfo=default))
+
+    return dates
+
+
+class Override(Event):
+    """Event overriding an occurrence of a recurring event.  The events of ics are identified by their UID alone, so
+    an override and its recurring event would be a single element of the events of a calendar.  An override is
+    identified by its UID and its RECURRENCE-ID instead
+    """
+
+    def __eq__(self, other):
+        return isinstance(other, Event) and identity(self) == identity(other)
+
+    def __hash__(self):
+        return hash(identity(self))
+
+
+def override(event: Event) -> Override:
+    """Turn an event with a RECURRENCE-ID into an Override, keeping its fields
+
+
+    :param event: the event
+    :type event: Event
+
+
+    :return: the override
+    :rtype: Override
+    """
+
+    result = Override.__new__(Override)
+    result.__dict__.update(event.__dict__)
+    return result
+
+
+def identity(event: Event) -> Tuple[str, str]:
+    """
+    :param event: the event
+    :type event: Event
+
+
+    :return: the UID of the event and the value of its RECURRENCE-ID, None if it has none
+    :rtype: Tuple[str, str]
+    """
+
+    return event.uid, next((line.value for line in event.extra if line.name == "RECURRENCE-ID"), None)
+
+
+def recurrence_id(event: Event) -> datetime:
+    """Find the occurrence of a recurring event that is overridden by an event
+
+
+    :param event: the event
+    :type event: Event
+
+
+    :return: the start of the overridden occurrence, None if the event does not override an occurrence or if its
+    RECURRENCE-ID cannot be interpreted, the event being then considered as a plain event
+    :rtype: datetime
+    """
+
+    for line in event.extra:
+        if line.name == "RECURRENCE-ID":
+            if event.begin is None:
+                logger.warning("Ignoring the RECURRENCE-ID of %s: the event has no start", event.uid)
+                return None
+
+            try:
+                return parse_dates(line, event.begin.tzinfo)[0]
+            except ValueError as e:
+                logger.warning("Ignoring the RECURRENCE-ID of %s: %s", event.uid, e)
+                return None
+
+    return None
+
+
+def is_recurring(event: Event) -> bool:
+    """Check whether an event has a recurrence
+
+
+    :param event: the event
+    :type event: Event
+
+
+    :return: True if the event has a RRULE or a RDATE property
+    :rtype: bool
+    """
+
+    return any(line.name in ("RRULE", "RDATE") for line in event.extra)
+
+
+def until(rule: str, start: datetime) -> str:
+    """Express the UNTIL part of a RRULE in UTC.  dateutil rejects a date or a local time as the UNTIL of a rule
+    whose start is aware, such as the date ending the recurrence of an all-day event.  A date includes the whole day,
+    a local time is in the timezone of the start
+
+
+    :param rule: the value of the RRULE property
+    :type rule: str
+
+    :param start: the start of the event
+    :type start: datetime
+
+
+    :return: the rule, with its UNTIL in UTC
+    :rtype: str
+    """
+
+    match = UNTIL.search(rule)
+    if match is None or start.tzinfo is None or match.group(1).endswith("Z"):
+        return rule
+
+    value = match.group(1)
+    if "T" in value:
+        limit = datetime.strptime(value, "%Y%m%dT%H%M%S").replace(tzinfo=start.tzinfo)
+    else:
+        limit = datetime.strptime(value, "%Y%m%d").replace(hour=23, minute=59, second=59, tzinfo=start.tzinfo)
+
+    return rule[:match.start(1)] + limit.astimezone(tz.tzutc()).strftime("%Y%m%dT%H%M%SZ") + rule[match.end(1):]
+
+
+def occurrences(event: Event, begin: arrow.Arrow, end: arrow.Arrow,
+                overridden: Iterable[datetime] = ()) -> List[Tuple[arrow.Arrow, arrow.Arrow]]:
+    """Compute the occurrences of an event overlapping a range of time
+
+
+    :param event: the event
+    :type event: Event
+
+    :param begin: the beginning of the range
+    :type begin: arrow.Arrow
+
+    :param end: the end of the range
+    :type end: arrow.Arrow
+
+    :param overridden: the starts of the occurrences overridden by other events, which are skipped
+    :type overridden: Iterable[datetime]
+
+
+    :return: the beginning and the end of every occurrence
+    :rtype: List[Tuple[arrow.Arrow, arrow.Arrow]]
+    """
+
+    if event.begin is None:
+        return []
+
+    start = event.begin.datetime
+    duration = event.end.datetime - start if event.end is not None else timedelta(0)
+    starts = [start]
+
+    if is_recurring(event):
+        rules = rruleset()
+        rules.rdate(start)
+
+        try:
+            for line in event.extra:
+                if line.name == "RRULE":
+                    rules.rrule(rrulestr(until(line.value, start), dtstart=start))
+                elif line.name == "RDATE":
+                    for date in parse_dates(line, start.tzinfo):
+                        rules.rdate(date)
+                elif line.name == "EXDATE":
+                    for date in parse_dates(line, start.tzinfo):
+                        rules.exdate(date)
+
+            starts = rules.between(begin.datetime - duration, end.datetime, inc=True)
+        except ValueError as e:
+            logger.warning("Could not expand the recurrence of %s: %s", event.uid, e)
+
+    overridden = set(overridden)
+    return [(arrow.get(s), arrow.get(s + duration)) for s in starts
+            if s not in overridden and s < end.datetime and (s + duration > begin.datetime or s >= begin.datetime)]
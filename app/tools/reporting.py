@@ -0,0 +1,90 @@
+"""This module reports the errors of the application to a Sentry compatible service.
+
+The reporting is optional: it is only enabled when a DSN is given and the sentry-sdk package is installed.  Once
+enabled, the uncaught exceptions are reported, as well as the downloads of a source failing repeatedly.
+"""
+
+import logging
+
+from tools.logs import redact
+
+try:
+    import sentry_sdk
+except ImportError:
+    sentry_sdk = None
+
+logger = logging.getLogger(__name__)
+
+_enabled = False
+_threshold = 3
+
+
+def scrub(event: dict, hint: dict) -> dict:
+    """Redact the URLs found in the messages of the exceptions before sending them
+
+
+    :param event: the event about to be sent
+    :type event: dict
+
+    :param hint: the original exception, unused
+    :type hint: dict
+
+
+    :return: the redacted event
+    :rtype: dict
+    """
+
+    for value in event.get("exception", {}).get("values", []):
+        if value.get("value"):
+            value["value"] = redact(value["value"])
+
+    return event
+
+
+def setup_reporting(dsn: str = None, threshold: int = 3) -> None:
+    """Enable the reporting of the errors
+
+
+    :param dsn: the DSN of the Sentry compatible service.  If not specified, the errors are not reported
+    :type dsn: str
+
+    :param threshold: number of consecutive failed downloads of a source before reporting them
+    :type threshold: int
+    """
+
+    global _enabled, _threshold
+
+    if not dsn:
+        return
+
+    if sentry_sdk is None:
+        logger.warning("The sentry-sdk package is not installed, the errors will not be reported")
+        return
+
+    sentry_sdk.init(dsn, before_send=scrub)
+    _enabled = True
+    _threshold = threshold
+
+
+def report_failure(entry: dict, error: Exception, failures: int) -> None:
+    """Report the failed download of a source if it failed at least as many times in a row as the threshold
+
+
+    :param entry: representation of the entry that could not be downloaded.  This is the Python representation of
+    the corresponding entry in the config file
+    :type entry: dict
+
+    :param error: the error that occurred
+    :type error: Exception
+
+    :param failures: number of consecutive failed downloads of the source
+    :type failures: int
+    """
+
+    if not _enabled or failures < _threshold:
+        return
+
+    with sentry_sdk.push_scope() as scope:
+        scope.set_tag("source", entry["name"])
+        scope.set_context("source", {"name": entry["name"], "url": redact(entry["url"]), "failures": failures})
+        sentry_sdk.capture_exception(error)
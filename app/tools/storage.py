@@ -0,0 +1,652 @@
+"""This module stores the cached calendars and the status of the sources.
+
+The files of the cache are stored by a backend: in the app/cache directory by default, in a SQLite database, in
+memory, or in Redis so several instances of the application behind a load balancer share the same cache.  The backend
+is chosen with the URL of the cache: a path for a directory, sqlite:///path for SQLite, memory:// for the memory and
+redis://host:port/db for Redis.  Other storages can be plugged with set_backend, by subclassing CacheBackend.
+
+Along with a file, the backends store the validators of its content: the ETag and the Last-Modified headers served
+with the calendar, and the hash of the calendar.  The files can be encrypted before being stored, with a key given
+to open_backend.
+"""
+
+import base64
+import json
+import logging
+import os
+import queue
+import sqlite3
+import tempfile
+import zlib
+from contextlib import contextmanager
+from typing import Iterator, List, Tuple
+
+from tools.clock import now
+
+try:
+    import redis
+except ImportError:
+    redis = None
+
+try:
+    import zstandard
+except ImportError:
+    zstandard = None
+
+try:
+    from cryptography.exceptions import InvalidTag
+    from cryptography.hazmat.primitives.ciphers.aead import ChaCha20Poly1305
+except ImportError:
+    ChaCha20Poly1305 = None
+
+logger = logging.getLogger(__name__)
+
+VALIDATORS = ("etag", "lastModified", "contentHash")
+ZSTD_MAGIC = b"\x28\xb5\x2f\xfd"
+ENCRYPTED = "encrypted:"
+SQLITE_POOL_SIZE = 4
+SQLITE_BUSY_TIMEOUT = 30
+
+# Statements upgrading the schema of the SQLite cache, the version of the schema being the number of applied
+# migrations.  New migrations are appended, the existing ones must never be modified
+MIGRATIONS = [
+    ["CREATE TABLE cache (key TEXT PRIMARY KEY, data TEXT, mtime REAL)"],
+    ["ALTER TABLE cache ADD COLUMN etag TEXT",
+     "ALTER TABLE cache ADD COLUMN last_modified TEXT",
+     "ALTER TABLE cache ADD COLUMN content_hash TEXT"],
+]
+
+
+class CacheBackend:
+    """Base class of the backends.  A backend stores the files of the cache, identified by their name
+    """
+
+    def get(self, key: str) -> str:
+        """Read a file of the cache
+
+
+        :param key: the name of the file
+        :type key: str
+
+
+        :return: the content of the file, None if the file does not exist
+        :rtype: str
+        """
+
+        raise NotImplementedError
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        """Write a file of the cache, replacing its previous content and validators
+
+
+        :param key: the name of the file
+        :type key: str
+
+        :param data: the content of the file
+        :type data: str
+
+        :param validators: the etag, lastModified and contentHash validators of the content, if known
+        :type validators: dict
+        """
+
+        raise NotImplementedError
+
+    def meta(self, key: str) -> dict:
+        """Describe a file of the cache
+
+
+        :param key: the name of the file
+        :type key: str
+
+
+        :return: the time of the last modification of the file as a timestamp (mtime), its size in bytes (size) and
+        the validators stored with the file, None if the file does not exist
+        :rtype: dict
+        """
+
+        raise NotImplementedError
+
+    def keys(self) -> List[str]:
+        """
+        :return: the names of the files of the cache
+        :rtype: List[str]
+        """
+
+        raise NotImplementedError
+
+    def delete(self, key: str) -> None:
+        """Remove a file of the cache, if it exists
+
+
+        :param key: the name of the file
+        :type key: str
+        """
+
+        raise NotImplementedError
+
+    def vacuum(self) -> None:
+        """Reclaim the space left by the removed files, for the storages that do not do it by themselves
+        """
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        """Set the time of the last modification of a file of the cache to the current time
+
+
+        :param key: the name of the file
+        :type key: str
+
+        :param validators: if specified, the validators replacing the ones stored with the file
+        :type validators: dict
+        """
+
+        raise NotImplementedError
+
+
+def known(validators: dict) -> dict:
+    """
+    :param validators: validators of a file of the cache, possibly None or with other values
+    :type validators: dict
+
+
+    :return: the etag, lastModified and contentHash validators that are set
+    :rtype: dict
+    """
+
+    return {name: (validators or {})[name] for name in VALIDATORS if (validators or {}).get(name) is not None}
+
+
+def compress(data: str) -> bytes:
+    """Compress the content of a file of the cache, with zstd if the zstandard module is installed, zlib otherwise
+
+
+    :param data: the content of the file
+    :type data: str
+
+
+    :return: the compressed content
+    :rtype: bytes
+    """
+
+    if zstandard is not None:
+        return zstandard.ZstdCompressor().compress(data.encode())
+
+    return zlib.compress(data.encode())
+
+
+def decompress(data) -> str:
+    """Decompress the content of a file of the cache compressed by compress.  Uncompressed contents, stored before
+    the compression of the cache, are returned as is
+
+
+    :param data: the stored content
+    :type data: Union[bytes, str]
+
+
+    :return: the content of the file
+    :rtype: str
+
+
+    :raises RuntimeError: if the content is compressed with zstd but the zstandard module is not installed
+    """
+
+    if isinstance(data, str):
+        return data
+
+    if data.startswith(ZSTD_MAGIC):
+        if zstandard is None:
+            raise RuntimeError("the zstandard module is required to read the cache compressed with zstd")
+        return zstandard.ZstdDecompressor().decompress(data).decode()
+
+    return zlib.decompress(data).decode()
+
+
+class FileBackend(CacheBackend):
+    """Backend storing the files of the cache in a directory.  The time a file was written and its validators are
+    stored next to it, in a .meta file, the time being given by the clock of the application like for the other
+    backends.  The files are written to a temporary file first, then renamed, so a request reading the cache while
+    it is being written gets the previous or the new version of a file, never a part of it
+    """
+
+    def __init__(self, directory: str = "app/cache"):
+        """
+        :param directory: the path of the directory, created if needed
+        :type directory: str
+        """
+
+        self.directory = directory
+
+    def path(self, key: str) -> str:
+        """
+        :param key: the name of the file
+        :type key: str
+
+
+        :return: the path of the file
+        :rtype: str
+
+
+        :raises ValueError: if the name would designate a file outside of the directory
+        """
+
+        if "/" in key or os.sep in key or (os.altsep and os.altsep in key) or key in ("", ".", ".."):
+            raise ValueError("Invalid name of a file of the cache: " + repr(key))
+
+        return os.path.join(self.directory, key)
+
+    def get(self, key: str) -> str:
+        if not os.path.isfile(self.path(key)):
+            return None
+
+        with open(self.path(key), 'r') as file:
+            return file.read()
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        if not os.path.isdir(self.directory):
+            os.makedirs(self.directory)
+
+        # The content is renamed before its .meta, so a request reading the cache in between never sees the new
+        # validators with the previous content
+        self.write((self.path(key), data), self.description(key, known(validators)))
+
+    def meta(self, key: str) -> dict:
+        if not os.path.isfile(self.path(key)):
+            return None
+
+        # The files written before the .meta files held the time fall back on the time of the file system
+        meta = {"mtime": os.path.getmtime(self.path(key)), "size": os.path.getsize(self.path(key))}
+        if os.path.isfile(self.path(key) + ".meta"):
+            with open(self.path(key) + ".meta", 'r') as file:
+                meta.update(json.load(file))
+
+        return meta
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        meta = self.meta(key)
+        self.write(self.description(key, known(validators) if validators is not None else known(meta)))
+
+    def keys(self) -> List[str]:
+        if not os.path.isdir(self.directory):
+            return []
+
+        return [name for name in os.listdir(self.directory) if not name.endswith(".meta") and not name.endswith(".tmp")]
+
+    def delete(self, key: str) -> None:
+        for path in (self.path(key), self.path(key) + ".meta"):
+            if os.path.isfile(path):
+                os.remove(path)
+
+    def description(self, key: str, validators: dict) -> Tuple[str, str]:
+        """
+        :param key: the name of the file
+        :type key: str
+
+        :param validators: the validators of the content of the file
+        :type validators: dict
+
+
+        :return: the path of the .meta file of the file and its content: the current time and the validators
+        :rtype: Tuple[str, str]
+        """
+
+        return self.path(key) + ".meta", json.dumps(dict(validators, mtime=now().timestamp))
+
+    def write(self, *files: Tuple[str, str]) -> None:
+        """Replace the content of files atomically.  Every file is written to a temporary file before the first one
+        is renamed, so the files are replaced one right after the other, in order
+
+
+        :param files: the path and the new content of every file
+        :type files: Tuple[str, str]
+        """
+
+        temporaries = []
+        try:
+            for path, data in files:
+                descriptor, temporary = tempfile.mkstemp(dir=self.directory, prefix=".", suffix=".tmp")
+                temporaries.append(temporary)
+                with os.fdopen(descriptor, 'w') as file:
+                    file.write(data)
+
+            for (path, _), temporary in zip(files, temporaries):
+                os.replace(temporary, path)
+        except BaseException:
+            for temporary in temporaries:
+                if os.path.isfile(temporary):
+                    os.remove(temporary)
+            raise
+
+
+class MemoryBackend(CacheBackend):
+    """Backend keeping the files of the cache in memory, e.g. for the tests.  The cache is lost when the application
+    stops
+    """
+
+    def __init__(self):
+        self.files = {}
+
+    def get(self, key: str) -> str:
+        return self.files[key][0] if key in self.files else None
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        self.files[key] = (data, dict(known(validators), mtime=now().timestamp))
+
+    def meta(self, key: str) -> dict:
+        return dict(self.files[key][1], size=len(self.files[key][0].encode())) if key in self.files else None
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        data, meta = self.files[key]
+        if validators is not None:
+            meta = known(validators)
+        self.files[key] = (data, dict(meta, mtime=now().timestamp))
+
+    def keys(self) -> List[str]:
+        return list(self.files)
+
+    def delete(self, key: str) -> None:
+        self.files.pop(key, None)
+
+
+class SQLiteBackend(CacheBackend):
+    """Backend storing the files of the cache in a table of a SQLite database, with a column per validator.  The
+    content of the files is compressed.  The schema of the database is upgraded when the backend is opened.
+    The database is opened in WAL mode with a pool of connections, so the requests reading the cache are not blocked
+    by the downloads writing to it.  The writes take the lock of the database when they start, waiting for the other
+    processes using the same database to release it, so several processes can share the cache
+    """
+
+    def __init__(self, path: str, size: int = SQLITE_POOL_SIZE):
+        """
+        :param path: the path of the database, created if needed
+        :type path: str
+
+        :param size: the number of connections of the pool.  An in-memory database has a single connection
+        :type size: int
+        """
+
+        self.pool = queue.Queue()
+        for _ in range(size if path != ":memory:" else 1):
+            connection = sqlite3.connect(path, timeout=SQLITE_BUSY_TIMEOUT, isolation_level="IMMEDIATE",
+                                         check_same_thread=False)
+            connection.execute("PRAGMA journal_mode=WAL")
+            self.pool.put(connection)
+
+        with self.connection() as connection:
+            self.migrate(connection)
+
+    @contextmanager
+    def connection(self) -> Iterator[sqlite3.Connection]:
+        """Borrow a connection from the pool, waiting for one to be available
+
+
+        :return: the connection, given back to the pool at the end of the with block
+        :rtype: Iterator[sqlite3.Connection]
+        """
+
+        connection = self.pool.get()
+        try:
+            yield connection
+        finally:
+            self.pool.put(connection)
+
+    @staticmethod
+    def version(connection: sqlite3.Connection) -> int:
+        """
+        :param connection: a connection to the database
+        :type connection: sqlite3.Connection
+
+
+        :return: the version of the schema of the database, 0 for an empty database
+        :rtype: int
+        """
+
+        if connection.execute("SELECT name FROM sqlite_master WHERE name = 'schema_version'").fetchone():
+            return connection.execute("SELECT version FROM schema_version").fetchone()[0]
+
+        # The databases created before the versioning of the schema have no schema_version table
+        columns = [row[1] for row in connection.execute("PRAGMA table_info(cache)")]
+        if not columns:
+            return 0
+
+        return 2 if "content_hash" in columns else 1
+
+    def migrate(self, connection: sqlite3.Connection) -> None:
+        """Apply the migrations that are missing from the database, in a single transaction holding the lock of the
+        database, so two processes opening the database at the same time do not migrate it twice
+
+
+        :param connection: a connection to the database
+        :type connection: sqlite3.Connection
+        """
+
+        with connection:
+            connection.execute("BEGIN IMMEDIATE")
+            version = self.version(connection)
+            if version > len(MIGRATIONS):
+                raise RuntimeError("the cache database was created by a newer version of ics-fusion")
+
+            connection.execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER)")
+            if not connection.execute("SELECT version FROM schema_version").fetchone():
+                connection.execute("INSERT INTO schema_version (version) VALUES (?)", (version,))
+
+            for number, statements in enumerate(MIGRATIONS[version:], version + 1):
+                logger.info("Migrating the cache database to version %d", number)
+                for statement in statements:
+                    connection.execute(statement)
+                connection.execute("UPDATE schema_version SET version = ?", (number,))
+
+    def get(self, key: str) -> str:
+        with self.connection() as connection:
+            row = connection.execute("SELECT data FROM cache WHERE key = ?", (key,)).fetchone()
+        return decompress(row[0]) if row is not None else None
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        validators = known(validators)
+        data = compress(data)
+        with self.connection() as connection, connection:
+            connection.execute("INSERT OR REPLACE INTO cache (key, data, mtime, etag, last_modified, content_hash) "
+                               "VALUES (?, ?, ?, ?, ?, ?)",
+                               (key, data, now().timestamp) + tuple(validators.get(name) for name in VALIDATORS))
+
+    def meta(self, key: str) -> dict:
+        with self.connection() as connection:
+            row = connection.execute("SELECT mtime, length(CAST(data AS BLOB)), etag, last_modified, content_hash "
+                                     "FROM cache WHERE key = ?", (key,)).fetchone()
+        return dict(known(dict(zip(VALIDATORS, row[2:]))), mtime=row[0], size=row[1]) if row is not None else None
+
+    def keys(self) -> List[str]:
+        with self.connection() as connection:
+            return [row[0] for row in connection.execute("SELECT key FROM cache")]
+
+    def delete(self, key: str) -> None:
+        with self.connection() as connection, connection:
+            connection.execute("DELETE FROM cache WHERE key = ?", (key,))
+
+    def vacuum(self) -> None:
+        with self.connection() as connection:
+            connection.execute("VACUUM")
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        with self.connection() as connection, connection:
+            connection.execute("UPDATE cache SET mtime = ? WHERE key = ?", (now().timestamp, key))
+            if validators is not None:
+                validators = known(validators)
+                connection.execute("UPDATE cache SET etag = ?, last_modified = ?, content_hash = ? WHERE key = ?",
+                                   tuple(validators.get(name) for name in VALIDATORS) + (key,))
+
+
+class RedisBackend(CacheBackend):
+    """Backend storing the files of the cache in Redis, as hashes with the content and the time of the last
+    modification of the files
+    """
+
+    def __init__(self, url: str, prefix: str = "ics-fusion:"):
+        """
+        :param url: the URL of the Redis database, redis://host:port/db
+        :type url: str
+
+        :param prefix: the prefix of the keys of the files
+        :type prefix: str
+        """
+
+        if redis is None:
+            raise RuntimeError("the redis module is required to use a Redis cache")
+
+        self.client = redis.Redis.from_url(url, decode_responses=True)
+        self.prefix = prefix
+
+    def get(self, key: str) -> str:
+        return self.client.hget(self.prefix + key, "data")
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        pipeline = self.client.pipeline()
+        pipeline.delete(self.prefix + key)
+        pipeline.hset(self.prefix + key, mapping=dict(known(validators), data=data, mtime=now().timestamp))
+        pipeline.execute()
+
+    def meta(self, key: str) -> dict:
+        meta = self.client.hmget(self.prefix + key, "mtime", *VALIDATORS)
+        if meta[0] is None:
+            return None
+
+        return dict(known(dict(zip(VALIDATORS, meta[1:]))), mtime=float(meta[0]),
+                    size=self.client.hstrlen(self.prefix + key, "data"))
+
+    def keys(self) -> List[str]:
+        return [key[len(self.prefix):] for key in self.client.scan_iter(self.prefix + "*")]
+
+    def delete(self, key: str) -> None:
+        self.client.delete(self.prefix + key)
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        self.client.hset(self.prefix + key, "mtime", now().timestamp)
+        if validators is not None:
+            self.client.hdel(self.prefix + key, *VALIDATORS)
+            if known(validators):
+                self.client.hset(self.prefix + key, mapping=known(validators))
+
+
+class EncryptedBackend(CacheBackend):
+    """Backend encrypting the files of the cache with ChaCha20-Poly1305 before storing them in another backend.  The
+    name of a file is authenticated with its content, so the content of a file cannot be swapped with another one.
+    The files that cannot be decrypted, for instance after a change of the key, are considered missing.  So are the
+    files that are not encrypted, unless they are explicitly accepted while migrating a cache to encryption
+    """
+
+    def __init__(self, storage: CacheBackend, key: str, plaintext: bool = False):
+        """
+        :param storage: the backend storing the encrypted files
+        :type storage: CacheBackend
+
+        :param key: the key, 32 bytes encoded in base64
+        :type key: str
+
+        :param plaintext: if true, the files stored before the encryption of the cache are read as is
+        :type plaintext: bool
+        """
+
+        if ChaCha20Poly1305 is None:
+            raise RuntimeError("the cryptography module is required to encrypt the cache")
+
+        try:
+            key = base64.b64decode(key, validate=True)
+        except ValueError as e:
+            raise ValueError("the key of the cache is not valid base64") from e
+        if len(key) != 32:
+            raise ValueError("the key of the cache must be 32 bytes long")
+
+        self.storage = storage
+        self.cipher = ChaCha20Poly1305(key)
+        self.plaintext = plaintext
+
+    def get(self, key: str) -> str:
+        data = self.storage.get(key)
+        if data is None:
+            return None
+
+        if not data.startswith(ENCRYPTED):
+            if self.plaintext:
+                return data
+            logger.error("Ignoring %s: the file is not encrypted", key)
+            return None
+
+        try:
+            blob = base64.b64decode(data[len(ENCRYPTED):])
+            return zlib.decompress(self.cipher.decrypt(blob[:12], blob[12:], key.encode())).decode()
+        except (InvalidTag, ValueError, zlib.error):
+            logger.error("Ignoring %s: the file cannot be decrypted with the key of the cache", key)
+            return None
+
+    def put(self, key: str, data: str, validators: dict = None) -> None:
+        nonce = os.urandom(12)
+        blob = nonce + self.cipher.encrypt(nonce, zlib.compress(data.encode()), key.encode())
+        self.storage.put(key, ENCRYPTED + base64.b64encode(blob).decode(), validators)
+
+    def meta(self, key: str) -> dict:
+        return self.storage.meta(key)
+
+    def touch(self, key: str, validators: dict = None) -> None:
+        self.storage.touch(key, validators)
+
+    def keys(self) -> List[str]:
+        return self.storage.keys()
+
+    def delete(self, key: str) -> None:
+        self.storage.delete(key)
+
+    def vacuum(self) -> None:
+        self.storage.vacuum()
+
+
+_backend = FileBackend()
+
+
+def open_backend(url: str, key: str = None, plaintext: bool = False) -> CacheBackend:
+    """Create the backend corresponding to the URL of a cache
+
+
+    :param url: redis://host:port/db for Redis, sqlite:///path for SQLite (sqlite:////path for an absolute path),
+    memory:// for the memory, the path of a directory otherwise
+    :type url: str
+
+    :param key: if specified, the files are encrypted with this key, 32 bytes encoded in base64
+    :type key: str
+
+    :param plaintext: if true, the files stored before the encryption of the cache are read as is
+    :type plaintext: bool
+
+
+    :return: the backend
+    :rtype: CacheBackend
+    """
+
+    if url.startswith("redis://") or url.startswith("rediss://"):
+        storage = RedisBackend(url)
+    elif url.startswith("sqlite:///"):
+        storage = SQLiteBackend(url[len("sqlite:///"):])
+    elif url == "memory://":
+        storage = MemoryBackend()
+    else:
+        storage = FileBackend(url)
+
+    return EncryptedBackend(storage, key, plaintext) if key else storage
+
+
+def set_backend(backend: CacheBackend) -> None:
+    """Replace the backend storing the cache
+
+
+    :param backend: the new backend
+    :type backend: CacheBackend
+    """
+
+    global _backend
+    _backend = backend
+
+
+def backend() -> CacheBackend:
+    """
+    :return: the backend storing the cache, the app/cache directory by default
+    :rtype: CacheBackend
+    """
+
+    return _backend
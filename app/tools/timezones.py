@@ -0,0 +1,162 @@
+"""This module resolves the TZID of the calendars to IANA timezones.
+
+The sources do not always define the timezones they reference: the VTIMEZONE component may be missing, or the TZID
+may be a Windows name or carry a vendor prefix (/mozilla.org/20050126_1/Europe/Brussels).  These TZIDs are resolved
+with the IANA timezone database bundled with dateutil, so the resolution does not depend on the timezone data of the
+host.
+
+The timezones defined by the sources are kept, so the merged calendar defines every timezone its events reference.
+"""
+
+import logging
+import re
+from datetime import tzinfo
+from typing import Dict
+
+from dateutil import tz
+from dateutil.zoneinfo import get_zonefile_instance
+
+logger = logging.getLogger(__name__)
+
+WINDOWS = {
+    "Dateline Standard Time": "Etc/GMT+12",
+    "Hawaiian Standard Time": "Pacific/Honolulu",
+    "Alaskan Standard Time": "America/Anchorage",
+    "Pacific Standard Time": "America/Los_Angeles",
+    "Mountain Standard Time": "America/Denver",
+    "US Mountain Standard Time": "America/Phoenix",
+    "Central Standard Time": "America/Chicago",
+    "Eastern Standard Time": "America/New_York",
+    "Atlantic Standard Time": "America/Halifax",
+    "Newfoundland Standard Time": "America/St_Johns",
+    "E. South America Standard Time": "America/Sao_Paulo",
+    "UTC": "Etc/UTC",
+    "Coordinated Universal Time": "Etc/UTC",
+    "GMT Standard Time": "Europe/London",
+    "Greenwich Standard Time": "Atlantic/Reykjavik",
+    "W. Europe Standard Time": "Europe/Berlin",
+    "Romance Standard Time": "Europe/Paris",
+    "Central Europe Standard Time": "Europe/Budapest",
+    "Central European Standard Time": "Europe/Warsaw",
+    "E. Europe Standard Time": "Europe/Chisinau",
+    "FLE Standard Time": "Europe/Kiev",
+    "GTB Standard Time": "Europe/Bucharest",
+    "Russian Standard Time": "Europe/Moscow",
+    "Israel Standard Time": "Asia/Jerusalem",
+    "South Africa Standard Time": "Africa/Johannesburg",
+    "Arabian Standard Time": "Asia/Dubai",
+    "India Standard Time": "Asia/Kolkata",
+    "China Standard Time": "Asia/Shanghai",
+    "Singapore Standard Time": "Asia/Singapore",
+    "Tokyo Standard Time": "Asia/Tokyo",
+    "Korea Standard Time": "Asia/Seoul",
+    "AUS Eastern Standard Time": "Australia/Sydney",
+    "New Zealand Standard Time": "Pacific/Auckland",
+}
+
+SUFFIX = re.compile(r"((?:Africa|America|Antarctica|Asia|Atlantic|Australia|Europe|Indian|Pacific|Etc)"
+                    r"(?:/[A-Za-z0-9_+-]+)+)$")
+TZID_PARAMETER = re.compile(r';TZID=("?)([^;:"]+)\1')
+TZID_PROPERTY = re.compile(r"^TZID[;:](?:.*:)?(.+)$", re.MULTILINE)
+VTIMEZONE = re.compile(r"^BEGIN:VTIMEZONE\s*$.*?^END:VTIMEZONE\s*$", re.MULTILINE | re.DOTALL | re.IGNORECASE)
+
+
+def iana_name(tzid: str) -> str:
+    """Find the IANA timezone corresponding to a TZID
+
+
+    :param tzid: the TZID, as found in a calendar
+    :type tzid: str
+
+
+    :return: the name of the IANA timezone, None if the TZID cannot be resolved
+    :rtype: str
+    """
+
+    zones = get_zonefile_instance().zones
+    tzid = tzid.strip()
+
+    if tzid in zones:
+        return tzid
+    if tzid in WINDOWS:
+        return WINDOWS[tzid]
+
+    match = SUFFIX.search(tzid)
+    if match and match.group(1) in zones:
+        return match.group(1)
+
+    return None
+
+
+def resolve(tzid: str) -> tzinfo:
+    """Find the timezone corresponding to a TZID
+
+
+    :param tzid: the TZID, as found in a calendar
+    :type tzid: str
+
+
+    :return: the timezone, None if the TZID cannot be resolved
+    :rtype: tzinfo
+    """
+
+    name = iana_name(tzid)
+    if name is not None:
+        return get_zonefile_instance().get(name)
+
+    return tz.gettz(tzid)
+
+
+def normalize(data: str) -> str:
+    """Replace the TZID referenced by a calendar without being defined in it by the corresponding IANA timezone
+
+
+    :param data: the content of the calendar
+    :type data: str
+
+
+    :return: the content of the calendar, with resolvable TZID
+    :rtype: str
+    """
+
+    defined = {tzid.strip() for tzid in TZID_PROPERTY.findall(data)}
+    unresolved = set()
+
+    def replace(match) -> str:
+        tzid = match.group(2)
+        if tzid in defined:
+            return match.group(0)
+
+        name = iana_name(tzid)
+        if name is None:
+            unresolved.add(tzid)
+            return match.group(0)
+
+        return ";TZID=" + name
+
+    data = TZID_PARAMETER.sub(replace, data)
+    for tzid in unresolved:
+        logger.warning("Could not resolve the timezone %s", tzid)
+
+    return data
+
+
+def definitions(data: str) -> Dict[str, str]:
+    """Extract the timezones defined by a calendar
+
+
+    :param data: the content of the calendar
+    :type data: str
+
+
+    :return: the VTIMEZONE components of the calendar with CRLF line endings, by TZID
+    :rtype: Dict[str, str]
+    """
+
+    found = {}
+    for match in VTIMEZONE.finditer(data):
+        tzid = TZID_PROPERTY.search(match.group(0))
+        if tzid is not None:
+            found.setdefault(tzid.group(1).strip(), "\r\n".join(match.group(0).splitlines()))
+
+    return found
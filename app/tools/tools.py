@@ -43,7 +43,9 @@ The JSON configuration file used in this module has the following structure
             "location":{
                 "addPrefix":"str",
                 "addSuffix":"str"
-            }
+            },
+            "color":"str",
+            "category":"str"
         }
     }
 ]
@@ -68,16 +70,59 @@ Only the url and the name field are mandatory.
 - description: modifications to apply to the description of the events
 - location: modification to apply to the location of the events
 - addPrefix: string to add at the beginning of the field
-- addSuffix: string to add at the end of the field
+- addSuffix: string to add at the end of the field, {source} being replaced by the name of the calendar
+- color: color given to the events, a CSS color name such as turquoise
+- category: category added to the categories of the events
 """
 
+import copy
 import json
+import logging
 import re
-from typing import List
+import threading
+from collections import OrderedDict
+from concurrent.futures import ThreadPoolExecutor
+from datetime import datetime
+from difflib import SequenceMatcher
+from hashlib import sha256
+from typing import Dict, Iterable, Iterator, List, NamedTuple, Tuple
+
+import arrow
+from ics import Calendar, Event
+from ics.grammar.parse import ContentLine
+from ics.utils import escape_string
+from tools import __version__
+from tools.caching import cache_key, load_cal, peek, read_cache, renew
+from tools.clock import now
+from tools.config import config_files, read_config, read_options
+from tools.errors import NotCachedError
+from tools.hooks import dispatch
+from tools.logs import span
+from tools.properties import clone, string_to_container
+from tools.recurrence import identity, is_recurring, occurrences, override, parse_dates, recurrence_id
+from tools.storage import backend
+
+logger = logging.getLogger(__name__)
+
+UTC = "YYYYMMDD[T]HHmmss[Z]"
+TRANSFORMED = 100
+
+_transformed = OrderedDict()
+_transformed_lock = threading.Lock()
+_generated = {}
+_concurrency = 8
+
+
+def set_concurrency(limit: int) -> None:
+    """Set the maximum number of sources of a calendar retrieved at the same time
+
+
+    :param limit: the maximum number of concurrent retrievals, 1 to retrieve the sources one at a time
+    :type limit: int
+    """
 
-from ics import Calendar
-from pathvalidate import sanitize_filename
-from tools.caching import load_cal
+    global _concurrency
+    _concurrency = max(1, limit)
 
 
 def filtering(cal: Calendar, filters: dict, field_name: str) -> Calendar:
@@ -111,6 +156,9 @@ def filtering(cal: Calendar, filters: dict, field_name: str) -> Calendar:
             return cal
 
         new = Calendar()
+        new.todos = cal.todos
+        new.vtimezones = getattr(cal, "vtimezones", {})
+        new.vjournals = getattr(cal, "vjournals", [])
 
         ignore_case = True if ("ignoreCase" in field and field["ignoreCase"]) else False
 
@@ -135,6 +183,7 @@ def filtering(cal: Calendar, filters: dict, field_name: str) -> Calendar:
                         and p.match(event.description) is not None:
                     new.events.add(event)
 
+        new.events = paired(cal.events, new.events)
         cal = new
         return cal
 
@@ -142,6 +191,39 @@ def filtering(cal: Calendar, filters: dict, field_name: str) -> Calendar:
         return cal
 
 
+def paired(events: Iterable[Event], kept: set) -> set:
+    """Keep the events overriding an occurrence of a recurring event along with the recurring event, so a filter does
+    not leave an override without its recurring event, nor drop an override and bring back the original occurrence.
+    The overrides of a recurring event missing from the calendar are filtered on their own
+
+
+    :param events: the events of the calendar before the filter
+    :type events: Iterable[Event]
+
+    :param kept: the events kept by the filter
+    :type kept: set
+
+
+    :return: the events to keep
+    :rtype: set
+    """
+
+    events = list(events)
+    masters = {event.uid for event in events if identity(event)[1] is None}
+    kept_masters = {event.uid for event in kept if identity(event)[1] is None}
+    kept_events = {id(event) for event in kept}
+
+    result = set()
+    for event in events:
+        if identity(event)[1] is not None and event.uid in masters:
+            if event.uid in kept_masters:
+                result.add(event)
+        elif id(event) in kept_events:
+            result.add(event)
+
+    return result
+
+
 def apply_filters(cal: Calendar, filters: dict) -> Calendar:
     """Apply all the filters to a calendar and returns the resulting calendar
 
@@ -251,6 +333,83 @@ def modify_text(cal: Calendar, modify: dict, field_name: str) -> Calendar:
     return cal
 
 
+def modify_uid(cal: Calendar, modify: dict) -> Calendar:
+    """Modify the UID of all the events in a calendar as specified in the modify structure, so the events of different
+    sources reusing the same UIDs do not collide.  The prefix and the suffix are added before the UID is hashed
+
+
+    :param cal: the calendar where it is needed to modify the UID of the events
+    :type cal: Calendar
+
+    :param modify: the structure defining how to modify the UID
+    :type modify: dict
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    if "uid" in modify:
+        change = modify["uid"]
+
+        for event in cal.events:
+            event.uid = change.get("addPrefix", "") + event.uid + change.get("addSuffix", "")
+            if change.get("hash"):
+                event.uid = sha256(event.uid.encode()).hexdigest() + "@ics-fusion"
+
+        # The events are hashed by UID, the set is rebuilt so the events can still be found in it
+        cal.events = set(cal.events)
+
+    return cal
+
+
+def modify_color(cal: Calendar, modify: dict) -> Calendar:
+    """Set the color of all the events in a calendar as specified in the modify structure, so the events of the
+    calendar remain distinguishable from the others in the merged calendar.  The color replaces the one of the events
+
+
+    :param cal: the calendar where it is needed to set the color of the events
+    :type cal: Calendar
+
+    :param modify: the structure defining the color of the events
+    :type modify: dict
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    if "color" in modify:
+        for event in cal.events:
+            event.extra[:] = [line for line in event.extra if line.name != "COLOR"]
+            event.extra.append(ContentLine(name="COLOR", value=modify["color"]))
+
+    return cal
+
+
+def modify_category(cal: Calendar, modify: dict) -> Calendar:
+    """Add a category to all the events in a calendar as specified in the modify structure, so the clients and the
+    filters of the merged calendar can tell which calendar the events come from
+
+
+    :param cal: the calendar where it is needed to add a category to the events
+    :type cal: Calendar
+
+    :param modify: the structure defining the category to add
+    :type modify: dict
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    if "category" in modify:
+        for event in cal.events:
+            event.categories.add(modify["category"])
+
+    return cal
+
+
 def apply_modify(cal: Calendar, modify: dict) -> Calendar:
     """Apply all the needed modifications to a calendar and returns the resulting calendar
 
@@ -270,17 +429,33 @@ def apply_modify(cal: Calendar, modify: dict) -> Calendar:
     cal = modify_text(cal, modify, "name")
     cal = modify_text(cal, modify, "description")
     cal = modify_text(cal, modify, "location")
+    cal = modify_uid(cal, modify)
+    cal = modify_color(cal, modify)
+    cal = modify_category(cal, modify)
     return cal
 
 
-def merge(cals: List[Calendar]) -> Calendar:
+def merge(cals: List[Calendar], conflicts: str = "sequence", similarity: float = None) -> Calendar:
     """Merge a list of calendars into a single calendar
-    Only takes the event into account, not the tasks or the alarms
+    Takes the events, the tasks (VTODO) and the journal entries (VJOURNAL) into account.
+    The events sharing the same UID and RECURRENCE-ID, such as the same event subscribed through two sources, appear
+    once, the version kept depending on the conflicts strategy.  The same goes for the tasks.  The events overriding
+    the occurrences of a recurring event come from the same calendar as the version of the recurring event kept.
+    The journal entries sharing the same UID appear once, the first one being kept.
+    The timezones defined by the calendars are written once, at the top of the merged calendar, the first definition
+    of a TZID being kept
 
 
     :param cals: the list of calendars to merge
     :type cals: List[Calendar]
 
+    :param conflicts: the strategy choosing the version of a duplicated event to keep, see CONFLICTS
+    :type conflicts: str
+
+    :param similarity: if specified, the events of different calendars with the same beginning and end are also
+    considered duplicated when the similarity of their names is at least this ratio, see resemble
+    :type similarity: float
+
 
     :return: the calendar containing the union of the events contained in the cals list
     :rtype: Calendar
@@ -289,47 +464,912 @@ def merge(cals: List[Calendar]) -> Calendar:
     :raises ValueError: if an element of the list is not a Calendar
     """
 
-    result = Calendar()
+    result = Calendar(creator="-//ics-fusion//ics-fusion " + __version__ + "//EN")
 
     for cal in cals:
         if not isinstance(cal, Calendar):
             raise ValueError("All elements should be Calendar")
-        result.events = result.events.union(cal.events)
+
+    result.events = deduplicate([cal.events for cal in cals], conflicts)
+    if similarity is not None:
+        result.events = resemble(cals, result.events, similarity)
+    result.todos = deduplicate([cal.todos for cal in cals], conflicts)
+
+    journals = set()
+    for cal in cals:
+        for journal in getattr(cal, "vjournals", []):
+            uid = re.search(r"^UID:(.*)$", journal, re.MULTILINE | re.IGNORECASE)
+            if uid is None or uid.group(1).strip() not in journals:
+                journals.add(uid.group(1).strip() if uid is not None else None)
+                result.extra.append(string_to_container(journal)[0])
+
+    result.vtimezones = {}
+    for cal in cals:
+        for tzid, definition in getattr(cal, "vtimezones", {}).items():
+            if tzid not in result.vtimezones:
+                result.vtimezones[tzid] = definition
+                result.extra.append(string_to_container(definition)[0])
 
     return result
 
 
-def process(path: str) -> Calendar:
+def deduplicate(calendars: List[Iterable], conflicts: str = "sequence") -> set:
+    """Keep a single version of the events or of the tasks sharing the same UID and RECURRENCE-ID, according to the
+    conflicts strategy.  When the strategy does not tell the versions apart, the first one is kept.
+    A recurring event and the events overriding its occurrences are kept together: the version of the recurring event
+    is chosen, and only the overrides of the calendar it comes from are kept, so the overrides of an outdated version
+    of the recurring event are not mixed with it
+
+
+    :param calendars: the events or the tasks of every calendar, in the order of the calendars
+    :type calendars: List[Iterable]
+
+    :param conflicts: the strategy choosing the version to keep, see CONFLICTS
+    :type conflicts: str
+
+
+    :return: the deduplicated events or tasks
+    :rtype: set
+
+
+    :raises ValueError: if the strategy is unknown
+    """
+
+    if conflicts not in CONFLICTS:
+        raise ValueError("Unknown conflicts strategy " + str(conflicts))
+    revision = CONFLICTS[conflicts]
+
+    masters = {}
+    for index, components in enumerate(calendars):
+        for component in components:
+            if identity(component)[1] is None:
+                uid = component.uid
+                if uid not in masters or revision(component) > revision(masters[uid][1]):
+                    masters[uid] = (index, component)
+
+    kept = {}
+    for index, components in enumerate(calendars):
+        for component in components:
+            key = identity(component)
+            if key[1] is not None and key[0] in masters and masters[key[0]][0] != index:
+                logger.debug("Dropping the override %s of an outdated recurring component", component.uid)
+                continue
+
+            if key not in kept or revision(component) > revision(kept[key]):
+                if key in kept:
+                    logger.debug("Keeping the most recent version of the duplicated component %s", component.uid)
+                kept[key] = component
+
+    return set(kept.values())
+
+
+def resemble(cals: List[Calendar], events: set, similarity: float) -> set:
+    """Remove the events duplicated across calendars under different UIDs, such as a public holiday found in several
+    calendars.  Two events of different calendars are duplicated if they have the same beginning and end and the
+    similarity of their names, ignoring the case and the punctuation, is at least the given ratio.  The event of the
+    calendar listed first is kept.  The overrides of the occurrences of a recurring event follow it
+
+
+    :param cals: the merged calendars, in the order of the config file
+    :type cals: List[Calendar]
+
+    :param events: the events of the calendars left after the deduplication by UID
+    :type events: set
+
+    :param similarity: the minimal similarity of the names, between 0 and 1
+    :type similarity: float
+
+
+    :return: the events without the duplicates
+    :rtype: set
+    """
+
+    remaining = {id(event) for event in events}
+    slots = {}
+    kept = set()
+
+    for index, cal in enumerate(cals):
+        for event in cal.events:
+            if id(event) not in remaining:
+                continue
+
+            name = re.sub(r"\W+", " ", (event.name or "").lower()).strip()
+            slot = slots.setdefault((event.begin, event.end), [])
+            if any(source != index and SequenceMatcher(None, name, other).ratio() >= similarity
+                   for source, other in slot):
+                logger.debug("Dropping the event %s, similar to an event of another calendar", event.uid)
+                continue
+
+            slot.append((index, name))
+            kept.add(event)
+
+    return paired(events, kept)
+
+
+def annotate(cal: Calendar, options: dict) -> Calendar:
+    """Add the properties describing the merged calendar to it, as set in the options of the config file: its name,
+    its description, its color and the interval at which the clients should refresh it
+
+
+    :param cal: the merged calendar
+    :type cal: Calendar
+
+    :param options: the options of the merged calendar
+    :type options: dict
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    if "name" in options:
+        cal.extra.append(ContentLine(name="NAME", value=escape_string(options["name"])))
+        cal.extra.append(ContentLine(name="X-WR-CALNAME", value=escape_string(options["name"])))
+
+    if "description" in options:
+        cal.extra.append(ContentLine(name="DESCRIPTION", value=escape_string(options["description"])))
+        cal.extra.append(ContentLine(name="X-WR-CALDESC", value=escape_string(options["description"])))
+
+    if "color" in options:
+        cal.extra.append(ContentLine(name="COLOR", value=options["color"]))
+        cal.extra.append(ContentLine(name="X-APPLE-CALENDAR-COLOR", value=options["color"]))
+
+    if "refresh" in options:
+        interval = "PT" + str(int(options["refresh"])) + "M"
+        cal.extra.append(ContentLine(name="REFRESH-INTERVAL", params={"VALUE": ["DURATION"]}, value=interval))
+        cal.extra.append(ContentLine(name="X-PUBLISHED-TTL", value=interval))
+
+    return cal
+
+
+def expand(cal: Calendar, window: dict) -> Calendar:
+    """Replace the recurring events of a calendar by their occurrences over a window of time, for the clients that do
+    not interpret the recurrence of the events.  The RRULE, RDATE and EXDATE properties are taken into account.  Each
+    occurrence is a distinct event, identified by the UID of the recurring event followed by the start of the
+    occurrence.  The events overriding an occurrence replace it and are identified the same way
+
+
+    :param cal: the merged calendar
+    :type cal: Calendar
+
+    :param window: the expand options of the merged calendar: the number of days before and after the current time
+    over which the occurrences are created, in the pastDays and the futureDays fields
+    :type window: dict
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    begin = now().to("utc").floor("day").shift(days=-window.get("pastDays", 30))
+    end = now().to("utc").floor("day").shift(days=window.get("futureDays", 365))
+
+    overridden = overrides(cal.events)
+    recurring = {event.uid for event in cal.events if is_recurring(event) and recurrence_id(event) is None}
+    events = set()
+
+    for event in cal.events:
+        if event.uid not in recurring:
+            events.add(event)
+        elif recurrence_id(event) is not None:
+            start = arrow.get(recurrence_id(event))
+            events.add(instance(event, event.begin, event.end, event.uid + "-" + start.to("utc").format(UTC)))
+        else:
+            for start, stop in occurrences(event, begin, end, overridden.get(event.uid, set())):
+                events.add(instance(event, start, stop, event.uid + "-" + start.to("utc").format(UTC)))
+
+    cal.events = events
+    return cal
+
+
+def instance(event: Event, begin: arrow.Arrow, end: arrow.Arrow, uid: str) -> Event:
+    """Copy an event as a single occurrence, without recurrence.  The event itself is left untouched, since it may be
+    reused for the next requests
+
+
+    :param event: the recurring event, or the event overriding one of its occurrences
+    :type event: Event
+
+    :param begin: the beginning of the occurrence
+    :type begin: arrow.Arrow
+
+    :param end: the end of the occurrence
+    :type end: arrow.Arrow
+
+    :param uid: the UID of the occurrence
+    :type uid: str
+
+
+    :return: the occurrence
+    :rtype: Event
+    """
+
+    occurrence = copy.copy(event)
+    occurrence.end = None
+    occurrence.begin = begin
+    occurrence.end = end
+    if event.all_day:
+        occurrence.make_all_day()
+
+    occurrence.uid = uid
+    recurrence = ("RRULE", "RDATE", "EXDATE", "RECURRENCE-ID")
+    occurrence.extra = copy.copy(event.extra)
+    occurrence.extra[:] = [line for line in event.extra if line.name not in recurrence]
+    return occurrence
+
+
+def freebusy(cal: Calendar, window: dict, path: str) -> Calendar:
+    """Add a VFREEBUSY component listing the periods during which the events of a calendar make its owner busy, over a
+    window of time.  The recurring events are expanded, and the transparent and the cancelled events are ignored.
+    Unless the events field of the window is true, the events, the tasks and the journal entries are removed, so only
+    the availability is shared
+
+
+    :param cal: the merged calendar
+    :type cal: Calendar
+
+    :param window: the freebusy options of the merged calendar: the number of days before and after the current time
+    covered by the VFREEBUSY component, in the pastDays and the futureDays fields
+    :type window: dict
+
+    :param path: name of the config file of the merged calendar, from which the UID of the component is derived
+    :type path: str
+
+
+    :return: the modified cal parameter
+    :rtype: Calendar
+    """
+
+    begin = now().to("utc").floor("day").shift(days=-window.get("pastDays", 0))
+    end = now().to("utc").floor("day").shift(days=window.get("futureDays", 60))
+
+    periods = []
+    overridden = overrides(cal.events)
+    for event in cal.events:
+        if getattr(event, "transparent", False) or getattr(event, "status", None) == "CANCELLED":
+            continue
+
+        exclude = overridden.get(event.uid, set()) if recurrence_id(event) is None else set()
+        for start, stop in occurrences(event, begin, end, exclude):
+            periods.append((max(start, begin).to("utc"), min(stop, end).to("utc")))
+
+    busy = []
+    for start, stop in sorted(periods):
+        if busy and start <= busy[-1][1]:
+            busy[-1] = (busy[-1][0], max(stop, busy[-1][1]))
+        else:
+            busy.append((start, stop))
+
+    lines = ["BEGIN:VFREEBUSY",
+             "UID:" + sha256(("freebusy-" + path).encode()).hexdigest() + "@ics-fusion",
+             "DTSTAMP:" + now().to("utc").format(UTC),
+             "DTSTART:" + begin.format(UTC),
+             "DTEND:" + end.format(UTC)]
+    lines += ["FREEBUSY;FBTYPE=BUSY:" + start.format(UTC) + "/" + stop.format(UTC) for start, stop in busy]
+    lines.append("END:VFREEBUSY")
+
+    if not window.get("events", False):
+        cal.events = set()
+        cal.todos = set()
+        cal.extra[:] = [item for item in cal.extra if item.name != "VJOURNAL"]
+
+    cal.extra.append(string_to_container("\r\n".join(lines))[0])
+    return cal
+
+
+def sequence(event: Event) -> int:
+    """
+    :param event: the event
+    :type event: Event
+
+
+    :return: the revision of the event, given by its SEQUENCE, 0 if it has none
+    :rtype: int
+    """
+
+    for line in event.extra:
+        if line.name == "SEQUENCE" and line.value.strip().isdigit():
+            return int(line.value)
+
+    return 0
+
+
+def modified(event: Event) -> str:
+    """
+    :param event: the event
+    :type event: Event
+
+
+    :return: the time of the last modification of the event, given by its LAST-MODIFIED or else its DTSTAMP, in the
+    UTC basic format (YYYYMMDDTHHMMSSZ), empty if it has none
+    :rtype: str
+    """
+
+    if getattr(event, "last_modified", None) is not None:
+        return event.last_modified.to("utc").format("YYYYMMDDTHHmmss") + "Z"
+
+    lines = {line.name: line.value for line in event.extra}
+    return lines.get("LAST-MODIFIED") or lines.get("DTSTAMP") or ""
+
+
+# The strategies choosing the version of a duplicated event to keep: a function giving the revision of an event, the
+# version with the highest revision being kept
+CONFLICTS = {
+    "sequence": sequence,
+    "modified": modified,
+    "priority": lambda event: 0,
+}
+
+
+class Transform:
+    """Base class of the transforms.  A transform filters or modifies the events of a calendar according to its
+    configuration, the value of the corresponding field in the entry of the config file
+    """
+
+    def __init__(self, config):
+        """
+        :param config: the configuration of the transform
+        """
+
+        self.config = config
+
+    def apply(self, cal: Calendar) -> Calendar:
+        """Apply the transform to a calendar
+
+
+        :param cal: the calendar to transform
+        :type cal: Calendar
+
+
+        :return: the transformed calendar
+        :rtype: Calendar
+        """
+
+        raise NotImplementedError
+
+
+class Filters(Transform):
+    """Transform applying the filters structure of an entry
+    """
+
+    def apply(self, cal: Calendar) -> Calendar:
+        return apply_filters(cal, self.config)
+
+
+class Modify(Transform):
+    """Transform applying the modify structure of an entry
+    """
+
+    def apply(self, cal: Calendar) -> Calendar:
+        return apply_modify(cal, self.config)
+
+
+class Padding(Transform):
+    """Transform adding buffer events, such as travel time, before and after the events matching a rule
+    """
+
+    def apply(self, cal: Calendar) -> Calendar:
+        field_name = self.config.get("field", "location")
+        pattern = re.compile(self.config["match"], re.DOTALL) if "match" in self.config else None
+        title = self.config.get("name", "Travel")
+
+        overridden = overrides(cal.events)
+
+        for event in list(cal.events):
+            value = getattr(event, field_name, None)
+            if event.begin is None or event.all_day or not value:
+                continue
+            if pattern is not None and pattern.match(value) is None:
+                continue
+
+            end = event.end if event.end is not None else event.begin
+            skipped = overridden.get(event.uid, set()) if recurrence_id(event) is None else set()
+            if self.config.get("before"):
+                cal.events.add(self.buffer(event, "before", event.begin.shift(minutes=-self.config["before"]),
+                                           event.begin, title, skipped))
+            if self.config.get("after"):
+                cal.events.add(self.buffer(event, "after", end, end.shift(minutes=self.config["after"]), title,
+                                           skipped))
+
+        return cal
+
+    @staticmethod
+    def buffer(event: Event, side: str, begin: arrow.Arrow, end: arrow.Arrow, title: str,
+               skipped: Iterable[datetime] = ()) -> Event:
+        """Create the buffer event of an event.  Its UID is derived from the UID and the RECURRENCE-ID of the event, so
+        the buffer is updated along with the event and the buffers of the occurrences overriding a recurring event are
+        distinct.  It repeats with the event, except on the excluded and the overridden occurrences
+
+
+        :param event: the event to pad
+        :type event: Event
+
+        :param side: before or after
+        :type side: str
+
+        :param begin: the beginning of the buffer
+        :type begin: arrow.Arrow
+
+        :param end: the end of the buffer
+        :type end: arrow.Arrow
+
+        :param title: the name of the buffer event
+        :type title: str
+
+        :param skipped: the starts of the occurrences of the event overridden by other events
+        :type skipped: Iterable[datetime]
+
+
+        :return: the buffer event
+        :rtype: Event
+        """
+
+        uid = identity(event)
+        buffer = Event(name=title, begin=begin, end=end,
+                       uid=side + "-" + uid[0] + ("-" + uid[1] if uid[1] is not None else ""))
+        skipped = list(skipped)
+        for line in event.extra:
+            if line.name == "RRULE":
+                buffer.extra.append(clone(line))
+            elif line.name == "EXDATE":
+                skipped += parse_dates(line, event.begin.tzinfo)
+
+        # The occurrences of the buffer start at the same offset from the occurrences of the event
+        if skipped and any(line.name == "RRULE" for line in buffer.extra):
+            offset = begin.datetime - event.begin.datetime
+            dates = sorted(arrow.get(date + offset).to("utc").format(UTC) for date in set(skipped))
+            buffer.extra.append(ContentLine(name="EXDATE", value=",".join(dates)))
+
+        return buffer
+
+
+TRANSFORMS = {
+    "filters": Filters,
+    "modify": Modify,
+    "padding": Padding,
+}
+
+
+def register_transform(name: str, transform: type) -> None:
+    """Make a transform available to the entries of the config files.  The transform is applied to the entries
+    having a field with the given name, and receives the value of this field as configuration
+
+
+    :param name: name of the field configuring the transform
+    :type name: str
+
+    :param transform: the class of the transform, a subclass of Transform
+    :type transform: type
+    """
+
+    TRANSFORMS[name] = transform
+
+
+def substitute(config, variables: dict):
+    """Replace the variables, such as {source}, in the strings of the configuration of a transform
+
+
+    :param config: the configuration of the transform, or a part of it
+    :type config: any
+
+    :param variables: the value of every variable, by name
+    :type variables: dict
+
+
+    :return: a copy of the configuration in which the variables are replaced
+    :rtype: any
+    """
+
+    if isinstance(config, dict):
+        return {key: substitute(value, variables) for key, value in config.items()}
+    if isinstance(config, list):
+        return [substitute(value, variables) for value in config]
+    if isinstance(config, str):
+        for name, value in variables.items():
+            config = config.replace("{" + name + "}", value)
+    return config
+
+
+def pipeline(entry: dict) -> List[Tuple[str, Transform]]:
+    """Build the ordered list of transforms to apply to an entry.  The order is given by the pipeline field of the
+    entry, the order in which the transforms were registered by default.  The transforms not configured in the entry
+    are skipped.  The {source} variable in the strings of their configuration is replaced by the name of the entry
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the name and the instance of every transform to apply, in order
+    :rtype: List[Tuple[str, Transform]]
+
+
+    :raises ValueError: if the pipeline field contains an unknown transform
+    """
+
+    transforms = []
+
+    for name in entry.get("pipeline", list(TRANSFORMS)):
+        if name not in TRANSFORMS:
+            raise ValueError("Unknown transform: " + name)
+
+        if name in entry:
+            config = substitute(entry[name], {"source": entry.get("name", "")})
+            transforms.append((name, TRANSFORMS[name](config)))
+
+    return transforms
+
+
+def transformed(entry: dict) -> Calendar:
+    """Load the calendar of an entry and apply its pipeline of transforms.  The result is kept in memory for the cached
+    entries and reused as long as neither the cached calendar nor the entry change, so only the sources that changed
+    are parsed and transformed again.  The expired calendars are renewed first, as when they are not reused.  The
+    TRANSFORMED most recently used results are kept
+
+
+    :param entry: representation of the entry.  This is the Python representation of the corresponding entry
+    in the config file
+    :type entry: dict
+
+
+    :return: the transformed calendar
+    :rtype: Calendar
+
+
+    :raises NotCachedError: if the entry was supposed to be cached but has not been cached before
+    :raises FetchError: if the calendar cannot be retrieved from the remote
+    :raises ParseError: if the calendar served by the remote cannot be decoded or parsed
+    """
+
+    key = None
+    if entry.get("cache"):
+        key = json.dumps(entry, sort_keys=True)
+        try:
+            renew(entry)
+        except NotCachedError:
+            with _transformed_lock:
+                _transformed.pop(key, None)
+            raise
+
+        # The mtime changes when the calendar is downloaded again, even if unchanged
+        meta = backend().meta(cache_key(entry, ".ics"))
+        version = (meta.get("contentHash"), meta["mtime"]) if meta is not None else None
+        with _transformed_lock:
+            if key in _transformed and _transformed[key][0] == version:
+                logger.debug("Reusing the transformed calendar of %s", entry["name"])
+                _transformed.move_to_end(key)
+                return _transformed[key][1]
+
+    cal = read_cache(entry) if key is not None else load_cal(entry)
+    if not entry.get("todos", True):
+        cal.todos = set()
+    if not entry.get("journals", True):
+        cal.vjournals = []
+
+    for _, transform in pipeline(entry):
+        cal = transform.apply(cal)
+
+    if key is not None:
+        with _transformed_lock:
+            _transformed[key] = (version, cal)
+            while len(_transformed) > TRANSFORMED:
+                _transformed.popitem(last=False)
+
+    return cal
+
+
+def process(path: str, sources: List[dict] = None) -> Calendar:
     """Open a config file from the specified path, download the calendars,
-    apply the filters, modify and merge the calendars as specified in the config file
+    apply the filters, modify and merge the calendars as specified in the config file.
+    The calendars are retrieved concurrently, see set_concurrency
 
 
     :param path: name of the file to open.  The file should be in the config/ folder
     :type path: str
 
+    :param sources: if specified, the name of every source, whether it came from the cache or from the remote and its
+    number of events after filtering are added to it
+    :type sources: List[dict]
+
 
     :return: the resulting calendar
     :rtype: Calendar
+
+
+    :raises NotCachedError: if a calendar was supposed to be cached but has not been cached before
+    :raises FetchError: if a calendar cannot be retrieved from the remote
+    :raises ParseError: if a calendar served by the remote cannot be decoded or parsed
+    """
+
+    with span("merge", calendar=path):
+        options = read_options(path)
+        result = annotate(combine(path, sources)[0], options)
+        if "expand" in options:
+            result = expand(result, options["expand"])
+        if "freebusy" in options:
+            result = freebusy(result, options["freebusy"], path)
+
+        uids = {event.uid for event in result.events}
+        previous = _generated.get(path, set())
+        _generated[path] = uids
+        dispatch("feed_generated", path, result, {"added": sorted(uids - previous), "removed": sorted(previous - uids)})
+
+        return result
+
+
+def combine(path: str, sources: List[dict] = None) -> Tuple[Calendar, Dict[int, str]]:
+    """Download the calendars of a config file, apply their transforms and merge them, keeping track of the source of
+    every event of the merged calendar
+
+
+    :param path: name of the config file.  The file should be in the config/ folder
+    :type path: str
+
+    :param sources: if specified, the name of every source, whether it came from the cache or from the remote and its
+    number of events after filtering are added to it
+    :type sources: List[dict]
+
+
+    :return: the merged calendar, and the name of the source of every event, by identity (id) of the event
+    :rtype: Tuple[Calendar, Dict[int, str]]
+
+
+    :raises NotCachedError: if a calendar was supposed to be cached but has not been cached before
+    :raises FetchError: if a calendar cannot be retrieved from the remote
+    :raises ParseError: if a calendar served by the remote cannot be decoded or parsed
     """
 
-    o = "app/config/" + sanitize_filename(path)
-    print("Try to open " + o)
-    file = open(o, "r")
-    config = json.loads(file.read())
-    file.close()
+    logger.debug("Try to open %s", path)
+    config = read_config(path)
 
     data = []
+    origins = {}
 
-    for entry in config:
+    with ThreadPoolExecutor(max_workers=min(_concurrency, max(1, len(config)))) as executor:
+        cals = list(executor.map(transformed, config))
 
-        cal = load_cal(entry)
+    for entry, cal in zip(config, cals):
+        if sources is not None:
+            sources.append({"name": entry["name"], "cached": bool(entry.get("cache")),
+                            "events": len(cal.events)})
 
-        if "filters" in entry:
-            cal = apply_filters(cal, entry["filters"])
+        data.append(cal)
+        for event in cal.events:
+            origins.setdefault(id(event), entry["name"])
 
-        if "modify" in entry:
-            cal = apply_modify(cal, entry["modify"])
+    for entry, cal in shadows(path):
+        if sources is not None:
+            sources.append({"name": entry["name"], "cached": bool(entry.get("cache")),
+                            "events": len(cal.events), "shadow": True})
 
         data.append(cal)
+        for event in cal.events:
+            origins.setdefault(id(event), entry["name"])
+
+    options = read_options(path)
+    return merge(data, options.get("conflicts", "sequence"), options.get("similarity")), origins
+
+
+def blocker(event: Event, name: str, path: str) -> Event:
+    """Create the anonymized copy of an event: only its timing and its recurrence are kept.  Its UID is derived from
+    the UID of the event and from the calendar it is copied to, so the updates of the event propagate to the copy
+
+
+    :param event: the event to copy
+    :type event: Event
+
+    :param name: the name of the copy
+    :type name: str
+
+    :param path: name of the config file of the calendar the copy is added to
+    :type path: str
+
+
+    :return: the anonymized copy
+    :rtype: Event
+    """
+
+    uid = sha256((path + "/" + event.uid).encode()).hexdigest() + "@ics-fusion"
+    copy = Event(name=name, begin=event.begin, end=event.end, uid=uid)
+    if event.all_day:
+        copy.make_all_day()
+    for line in event.extra:
+        if line.name in ("RRULE", "RDATE", "EXDATE", "RECURRENCE-ID"):
+            copy.extra.append(clone(line))
+
+    return override(copy) if recurrence_id(event) is not None else copy
+
+
+def shadows(path: str) -> List[Tuple[dict, Calendar]]:
+    """Find the entries of the other config files that are shadowed in a calendar, and build the calendars of their
+    anonymized copies
+
+
+    :param path: name of the config file of the calendar
+    :type path: str
+
+
+    :return: every shadowed entry, with the calendar of the copies of its events
+    :rtype: List[Tuple[dict, Calendar]]
+    """
+
+    result = []
+    for other in config_files():
+        if other == path:
+            continue
+
+        for entry in read_config(other):
+            shadow = entry.get("shadow")
+            if not shadow or path not in shadow.get("feeds", []):
+                continue
+
+            cal = Calendar()
+            for event in transformed(entry).events:
+                cal.events.add(blocker(event, shadow.get("name", "Blocked"), path))
+            result.append((entry, cal))
+
+    return result
+
+
+def describe(event: Event) -> dict:
+    """Take a snapshot of the fields of an event that can be modified by the configuration
+
+
+    :param event: the event to describe
+    :type event: Event
+
+
+    :return: the value of the name, description, location, begin and end fields of the event
+    :rtype: dict
+    """
+
+    return {"name": event.name, "description": event.description, "location": event.location,
+            "begin": str(event.begin), "end": str(event.end)}
+
+
+def explain(query: str, path: str = None) -> List[str]:
+    """Trace the events matching the query through the filters and modifications of the config files and explain
+    why they are included in or excluded from each feed.  The cache is not modified
+
+
+    :param query: UID of the event, or RegEx searched in the name of the events
+    :type query: str
+
+    :param path: name of the config file to consider.  If not specified, every config file is considered
+    :type path: str
+
+
+    :return: the lines of the explanation, empty if no event matches the query
+    :rtype: List[str]
+
+
+    :raises NotCachedError: if a calendar was supposed to be cached but has not been cached before
+    :raises FetchError: if a calendar cannot be retrieved from the remote
+    :raises ParseError: if a calendar served by the remote cannot be decoded or parsed
+    """
+
+    try:
+        p = re.compile(query, re.IGNORECASE)
+    except re.error:
+        # UIDs often contain characters such as + or [, they are then searched literally
+        p = re.compile(re.escape(query), re.IGNORECASE)
+    lines = []
+
+    for file in [path] if path is not None else config_files():
+        for entry in read_config(file):
+            cal = peek(entry)
+
+            for event in cal.events:
+                if event.uid != query and (event.name is None or p.search(event.name) is None):
+                    continue
+
+                lines.append("Event '" + str(event.name) + "' (" + str(event.uid) + ") from source '"
+                             + entry["name"] + "' in " + file)
+
+                single = Calendar()
+                single.events.add(event)
+                included = True
+
+                for name, transform in pipeline(entry):
+                    if isinstance(transform, Filters):
+                        for field_name in ["name", "description"]:
+                            if field_name not in transform.config:
+                                continue
+
+                            single = filtering(single, transform.config, field_name)
+                            if not single.events:
+                                lines.append("  filter on " + field_name + ": excluded "
+                                             + str(transform.config[field_name]))
+                                break
+
+                            lines.append("  filter on " + field_name + ": kept " + str(transform.config[field_name]))
+                    else:
+                        before = describe(event)
+                        single = transform.apply(single)
+                        after = describe(event)
+
+                        if not single.events:
+                            lines.append("  " + name + ": excluded")
+
+                        for field in before:
+                            if before[field] != after[field]:
+                                lines.append("  " + name + " " + field + ": " + repr(before[field]) + " -> "
+                                             + repr(after[field]))
+
+                    if not single.events:
+                        included = False
+                        break
+
+                lines.append("  => " + ("included in " if included else "excluded from ") + file)
+
+    return lines
+
+
+class FusedEvent(NamedTuple):
+    """Occurrence of an event of a merged calendar
+    """
+
+    uid: str
+    name: str
+    begin: arrow.Arrow
+    end: arrow.Arrow
+    location: str
+    description: str
+    categories: List[str]
+    source: str
+
+
+def overrides(events: Iterable[Event]) -> Dict[str, set]:
+    """Find the occurrences of the recurring events that are overridden by other events
+
+
+    :param events: the events of a calendar
+    :type events: Iterable[Event]
+
+
+    :return: the starts of the overridden occurrences, by UID
+    :rtype: Dict[str, set]
+    """
+
+    overridden = {}
+    for event in events:
+        if recurrence_id(event) is not None:
+            overridden.setdefault(event.uid, set()).add(recurrence_id(event))
+
+    return overridden
+
+
+def iter_events(path: str, begin: arrow.Arrow, end: arrow.Arrow) -> Iterator[FusedEvent]:
+    """Iterate over the occurrences of the events of the calendar described by a config file, in a range of time.
+    The events are those of the merged calendar, as served.  The recurring events are expanded into their
+    occurrences, and the occurrences overridden by another event are skipped
+
+
+    :param path: name of the config file.  The file should be in the config/ folder
+    :type path: str
+
+    :param begin: the beginning of the range
+    :type begin: arrow.Arrow
+
+    :param end: the end of the range
+    :type end: arrow.Arrow
+
+
+    :return: the occurrences overlapping the range, with the name of the source of their event
+    :rtype: Iterator[FusedEvent]
+
+
+    :raises NotCachedError: if a calendar was supposed to be cached but has not been cached before
+    :raises FetchError: if a calendar cannot be retrieved from the remote
+    :raises ParseError: if a calendar served by the remote cannot be decoded or parsed
+    """
+
+    cal, origins = combine(path)
 
-    return merge(data)
+    overridden = overrides(cal.events)
+    for event in cal.events:
+        exclude = overridden.get(event.uid, set()) if recurrence_id(event) is None else set()
+        for start, stop in occurrences(event, begin, end, exclude):
+            yield FusedEvent(event.uid, event.name, start, stop, event.location, event.description,
+                             sorted(event.categories or []), origins.get(id(event)))
@@ -0,0 +1,303 @@
+"""This module provides methods to check that a serialized calendar follows the iCalendar format (RFC 5545).
+
+Only the rules that matter to the clients subscribing to the feeds are checked: the line endings, the folding of
+the long lines, the nesting of the components and the required properties of the calendar and of the events.
+
+It also provides a lint of the calendars served by the remotes, pointing out the defects of a source and the
+options of the config file that can work around them.
+"""
+
+import re
+from datetime import datetime
+from io import StringIO
+from typing import List
+
+from dateutil import tz
+from ics import Calendar
+
+from tools.timezones import iana_name, resolve
+
+REQUIRED = {
+    "VCALENDAR": ["PRODID", "VERSION"],
+    "VEVENT": ["UID", "DTSTAMP", "DTSTART"],
+    "VTODO": ["UID", "DTSTAMP"],
+}
+
+LONG_DESCRIPTION = 10000
+
+PENALTIES = {
+    "fetch": 100,
+    "encoding": 50,
+    "missing-uid": 20,
+    "duplicate": 15,
+    "timezone": 15,
+    "dst": 15,
+    "long-description": 5,
+    "line-ending": 5,
+}
+
+
+def unfold(data: str) -> List[str]:
+    """Split a serialized calendar into content lines, joining the folded lines
+
+
+    :param data: the serialized calendar
+    :type data: str
+
+
+    :return: the unfolded content lines
+    :rtype: List[str]
+    """
+
+    lines = []
+    for line in data.replace("\r\n", "\n").split("\n"):
+        if line[:1] in (" ", "\t") and lines:
+            lines[-1] += line[1:]
+        elif line:
+            lines.append(line)
+
+    return lines
+
+
+def fold(line: str) -> List[str]:
+    """Fold a content line into physical lines of at most 75 octets, the continuation lines starting with a space.
+    The line is never cut inside a UTF-8 sequence
+
+
+    :param line: the content line
+    :type line: str
+
+
+    :return: the physical lines
+    :rtype: List[str]
+    """
+
+    data = line.encode()
+    parts = []
+    limit = 75
+    while len(data) > limit:
+        cut = limit
+        while data[cut] & 0xC0 == 0x80:
+            cut -= 1
+        parts.append(data[:cut].decode())
+        data = data[cut:]
+        limit = 74
+    parts.append(data.decode())
+
+    return parts[:1] + [" " + part for part in parts[1:]]
+
+
+def serialize(cal: Calendar) -> str:
+    """Serialize a calendar as it is served: the content lines longer than 75 octets are folded and every line,
+    including the last one, is terminated by CRLF
+
+
+    :param cal: the calendar
+    :type cal: Calendar
+
+
+    :return: the serialized calendar
+    :rtype: str
+    """
+
+    return "".join(physical + "\r\n" for line in unfold(str(cal)) for physical in fold(line))
+
+
+def validate(data: str) -> List[str]:
+    """Check that a serialized calendar follows the iCalendar format
+
+
+    :param data: the serialized calendar, exactly as it is served
+    :type data: str
+
+
+    :return: the description of the problems found, empty if the calendar is valid
+    :rtype: List[str]
+    """
+
+    problems = []
+
+    physical = data.replace("\r\n", "\n").split("\n")
+    bare = data.replace("\r\n", "").count("\n") + data.replace("\r\n", "").count("\r")
+    if bare > 0:
+        problems.append(str(bare) + " lines are not terminated by CRLF")
+    if not data.endswith("\r\n"):
+        problems.append("the calendar does not end with CRLF")
+
+    for number, line in enumerate(physical, start=1):
+        if len(line.encode()) > 75:
+            problems.append("line " + str(number) + " is longer than 75 octets and should be folded")
+
+    lines = unfold(data)
+    if not lines or lines[0] != "BEGIN:VCALENDAR" or lines[-1] != "END:VCALENDAR":
+        problems.append("the calendar is not enclosed in BEGIN:VCALENDAR and END:VCALENDAR")
+
+    stack = []
+    for line in lines:
+        name, _, value = line.partition(":")
+        name = name.split(";")[0].upper()
+
+        if name == "BEGIN":
+            stack.append((value.upper(), {}))
+        elif name == "END":
+            if not stack or stack[-1][0] != value.upper():
+                problems.append("END:" + value + " does not match any BEGIN")
+                continue
+
+            component, properties = stack.pop()
+            label = component + " " + properties["UID"] if "UID" in properties else component
+            for required in REQUIRED.get(component, []):
+                if required not in properties:
+                    problems.append(label + " has no " + required)
+        elif stack:
+            stack[-1][1][name] = value
+
+    for component, _ in stack:
+        problems.append("BEGIN:" + component + " is never closed")
+
+    return problems
+
+
+def lint(data: str) -> List[dict]:
+    """Look for the defects of a calendar served by a remote
+
+
+    :param data: the calendar, as served by the remote
+    :type data: str
+
+
+    :return: the defects found, with the name of the check, a description and a suggestion to work around it
+    :rtype: List[dict]
+    """
+
+    findings = []
+
+    bare = data.replace("\r\n", "").count("\n")
+    if bare > 0:
+        findings.append({"check": "line-ending", "detail": str(bare) + " lines are not terminated by CRLF",
+                         "suggestion": "none needed, the calendar is serialized again before being served"})
+
+    timezones = set()
+    references = set()
+    definitions = {}
+    definition = None
+    times = []
+    events = []
+    for line in unfold(data):
+        name, _, value = line.partition(":")
+        tzids = re.findall(r";TZID=\"?([^;:\"]+)", name, re.IGNORECASE)
+        references.update(tzids)
+        name = name.split(";")[0].upper()
+
+        if name == "BEGIN" and value.upper() == "VTIMEZONE":
+            definition = []
+        if definition is not None:
+            definition.append(line)
+        if name == "END" and value.upper() == "VTIMEZONE" and definition is not None:
+            tzid = next((l.partition(":")[2] for l in definition if l.upper().startswith("TZID")), None)
+            definitions[tzid] = "\r\n".join(definition)
+            definition = None
+
+        if name == "BEGIN" and value.upper() == "VEVENT":
+            events.append({})
+        elif name == "TZID":
+            timezones.add(value)
+        elif events and name in ("DTSTART", "DTEND") and tzids and re.match(r"^\d{8}T\d{6}$", value):
+            times.append((tzids[0], datetime.strptime(value, "%Y%m%dT%H%M%S")))
+        elif events and name in ("UID", "RECURRENCE-ID", "DESCRIPTION", "SUMMARY"):
+            events[-1][name] = value
+
+    missing = [e for e in events if "UID" not in e]
+    if missing:
+        findings.append({"check": "missing-uid", "detail": str(len(missing)) + " events have no UID",
+                         "suggestion": "ask the publisher to add UIDs: the UIDs given to these events are derived "
+                                       "from their content and change whenever they are modified"})
+
+    seen = set()
+    duplicates = 0
+    for event in events:
+        key = (event.get("UID"), event.get("RECURRENCE-ID"))
+        if key[0] is not None and key in seen:
+            duplicates += 1
+        seen.add(key)
+    if duplicates:
+        findings.append({"check": "duplicate", "detail": str(duplicates) + " events share their UID with another",
+                         "suggestion": "use 'filters' to exclude the duplicated events"})
+
+    unknown = sorted(r for r in references if r not in timezones and resolve(r) is None)
+    if unknown:
+        findings.append({"check": "timezone", "detail": "undefined timezones: " + ", ".join(unknown),
+                         "suggestion": "use 'modify.time.shift' to correct the offset of the events"})
+
+    gaps, disagreements = dst_anomalies(times, definitions)
+    if gaps:
+        findings.append({"check": "dst", "detail": str(gaps) + " event times fall into a DST gap",
+                         "suggestion": "use 'modify.time.shift' to correct the offset of the events"})
+    if disagreements:
+        findings.append({"check": "dst", "detail": str(disagreements) + " event times have an offset defined by the "
+                                                                         "calendar that disagrees with the IANA data",
+                         "suggestion": "use 'modify.time.shift' to correct the offset of the events"})
+
+    long = [e for e in events if len(e.get("DESCRIPTION", "")) > LONG_DESCRIPTION]
+    if long:
+        detail = str(len(long)) + " events have a description longer than " + str(LONG_DESCRIPTION) + " characters"
+        findings.append({"check": "long-description", "detail": detail,
+                         "suggestion": "use 'filters.description' to exclude the events"})
+
+    return findings
+
+
+def dst_anomalies(times: List[tuple], definitions: dict) -> tuple:
+    """Look for the local times of the events that do not exist because of a DST transition, and for the local times
+    whose offset according to the VTIMEZONE of the calendar differs from the offset according to the IANA data
+
+
+    :param times: the TZID and the local time of the DTSTART and DTEND properties of the events
+    :type times: List[tuple]
+
+    :param definitions: the VTIMEZONE components of the calendar, by TZID
+    :type definitions: dict
+
+
+    :return: the number of local times in a DST gap and the number of local times with a disagreeing offset
+    :rtype: tuple
+    """
+
+    gaps = 0
+    disagreements = 0
+    defined = {}
+    for tzid, text in definitions.items():
+        try:
+            defined[tzid] = tz.tzical(StringIO(text)).get()
+        except (ValueError, TypeError):
+            pass
+
+    for tzid, local in times:
+        name = iana_name(tzid)
+        zone = resolve(name) if name is not None else None
+        if zone is None:
+            continue
+
+        if not tz.datetime_exists(local, zone):
+            gaps += 1
+        elif tzid in defined:
+            offset = defined[tzid].utcoffset(local)
+            if offset is not None and offset != zone.utcoffset(local):
+                disagreements += 1
+
+    return gaps, disagreements
+
+
+def score(findings: List[dict]) -> int:
+    """Compute the quality score of a source from the defects found by the lint
+
+
+    :param findings: the defects found by the lint
+    :type findings: List[dict]
+
+
+    :return: the score, from 0 to 100
+    :rtype: int
+    """
+
+    return max(0, 100 - sum(PENALTIES[finding["check"]] for finding in findings))
@@ -0,0 +1,57 @@
+import json
+import unittest
+
+import arrow
+
+from tests.transport import fake_transport
+from tools.alerts import check_alert
+from tools.clock import Clock, FixedClock, set_clock
+from tools.fetchers import set_transport, transport
+
+WEBHOOK = "https://hooks.example.com/alerts"
+
+
+class AlertTest(unittest.TestCase):
+    def setUp(self):
+        self.previous = transport()
+        session, self.adapter = fake_transport({WEBHOOK: (200, "", {})})
+        set_transport(session)
+        self.clock = FixedClock(arrow.get("2021-01-01T00:00:00+00:00"))
+        set_clock(self.clock)
+
+    def tearDown(self):
+        set_clock(Clock())
+        set_transport(self.previous)
+
+    def posted(self) -> list:
+        return [json.loads(request.body)["event"] for request in self.adapter.requests]
+
+    def test_alert_after_the_threshold_then_recovery(self):
+        entry = {"name": "test", "alert": {"after": 60, "webhook": WEBHOOK}}
+        status = {"error": "HTTP 500", "failingSince": "2021-01-01T00:00:00+00:00"}
+
+        self.clock.advance(minutes=30)
+        check_alert(entry, status)
+        self.assertEqual(self.posted(), [])
+
+        self.clock.advance(minutes=30)
+        check_alert(entry, status)
+        check_alert(entry, status)
+        self.assertEqual(self.posted(), ["alert"])
+
+        status["error"] = None
+        check_alert(entry, status)
+        self.assertEqual(self.posted(), ["alert", "recovery"])
+
+    def test_freshness_sla(self):
+        entry = {"name": "test", "freshnessSla": 60, "alert": {"webhook": WEBHOOK}}
+        status = {"error": None, "lastSuccess": "2021-01-01T00:00:00+00:00"}
+
+        self.clock.advance(minutes=59)
+        check_alert(entry, status)
+        self.assertEqual(self.posted(), [])
+
+        self.clock.advance(minutes=2)
+        check_alert(entry, status)
+        check_alert(entry, status)
+        self.assertEqual(self.posted(), ["stale"])
@@ -0,0 +1,280 @@
+import gzip
+import json
+import os
+import tempfile
+import unittest
+
+import arrow
+
+from tests.transport import fake_transport
+from tools.caching import age, cache, cache_key, export_cache, get_from_cache, import_cache, load_cal, prune, \
+    read_status, restore, set_cache_limits, snapshot_time, snapshots
+from tools.clock import Clock, FixedClock, set_clock
+from tools.fetchers import set_transport, transport
+from tools.storage import backend
+
+URL = "https://calendar.example.com/feed.ics"
+
+
+def calendar(*uids: str) -> str:
+    events = ["BEGIN:VEVENT\r\nUID:" + uid + "\r\nDTSTART:20210101T100000Z\r\nSUMMARY:Meeting\r\nEND:VEVENT\r\n"
+              for uid in uids]
+    return "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n" + "".join(events) + "END:VCALENDAR\r\n"
+
+
+class CacheTest(unittest.TestCase):
+    def setUp(self):
+        self.cwd = os.getcwd()
+        self.directory = tempfile.TemporaryDirectory()
+        os.chdir(self.directory.name)
+        os.mkdir("app")
+
+        self.previous = transport()
+        self.clock = FixedClock(arrow.get("2021-01-01T00:00:00+00:00"))
+        set_clock(self.clock)
+
+    def tearDown(self):
+        set_cache_limits()
+        set_clock(Clock())
+        set_transport(self.previous)
+        os.chdir(self.cwd)
+        self.directory.cleanup()
+
+    def serve(self, *answers):
+        session, adapter = fake_transport({URL: list(answers)})
+        set_transport(session)
+        return adapter
+
+    def uids(self, entry: dict) -> list:
+        return sorted(event.uid for event in get_from_cache(entry).events)
+
+    def test_age_follows_the_clock(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        self.serve((200, calendar("1@test"), {}))
+
+        cache(entry)
+        self.clock.advance(minutes=30)
+
+        self.assertIsNotNone(backend().meta(cache_key(entry, ".ics")))
+        self.assertEqual(age(entry), 1800)
+        self.assertEqual(read_status(entry)["lastSuccess"], "2021-01-01T00:00:00+00:00")
+
+    def test_unchanged_content_refreshes_the_cache(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        self.serve((200, calendar("1@test"), {}))
+
+        cache(entry)
+        self.clock.advance(minutes=30)
+        cache(entry)
+
+        self.assertEqual(age(entry), 0)
+        self.assertEqual(read_status(entry)["lastSuccess"], "2021-01-01T00:30:00+00:00")
+
+    def test_failure_is_recorded(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        self.serve((500, "Internal Server Error", {}))
+
+        cache(entry)
+        self.clock.advance(minutes=10)
+        cache(entry)
+
+        status = read_status(entry)
+        self.assertEqual(status["consecutiveFailures"], 2)
+        self.assertEqual(status["failingSince"], "2021-01-01T00:00:00+00:00")
+        self.assertIsNone(backend().meta(cache_key(entry, ".ics")))
+
+    def test_guard_keeps_the_previous_data(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "guard": {"maxDrop": 50}}
+        self.serve((200, calendar("1@test", "2@test", "3@test"), {}), (200, calendar("1@test"), {}))
+
+        cache(entry)
+        kept = backend().get(cache_key(entry, ".ics"))
+        cache(entry)
+
+        self.assertEqual(backend().get(cache_key(entry, ".ics")), kept)
+        self.assertIn("dropped from 3 to 1", read_status(entry)["error"])
+
+    def test_guard_rejects_an_empty_calendar(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "guard": {"rejectEmpty": True}}
+        self.serve((200, calendar("1@test"), {}), (200, calendar(), {}))
+
+        cache(entry)
+        cache(entry)
+
+        status = read_status(entry)
+        self.assertEqual(status["metrics"]["events"], 1)
+        self.assertEqual(status["error"], "the calendar is empty while it had 1 events")
+
+    def test_guard_accepts_a_first_empty_calendar(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "guard": {"rejectEmpty": True}}
+        self.serve((200, calendar(), {}))
+
+        cache(entry)
+
+        self.assertIsNone(read_status(entry)["error"])
+        self.assertIsNotNone(backend().meta(cache_key(entry, ".ics")))
+
+    def test_conditional_request(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        headers = {"ETag": '"v1"', "Last-Modified": "Fri, 01 Jan 2021 00:00:00 GMT"}
+        adapter = self.serve((200, calendar("1@test"), headers), (304, "", {}))
+
+        cache(entry)
+        self.clock.advance(minutes=30)
+        cache(entry)
+
+        self.assertNotIn("If-None-Match", adapter.requests[0].headers)
+        self.assertEqual(adapter.requests[1].headers["If-None-Match"], '"v1"')
+        self.assertEqual(adapter.requests[1].headers["If-Modified-Since"], "Fri, 01 Jan 2021 00:00:00 GMT")
+        self.assertEqual(age(entry), 0)
+        status = read_status(entry)
+        self.assertIsNone(status["error"])
+        self.assertEqual(status["metrics"]["etag"], '"v1"')
+
+    def test_conditional_request_without_cached_copy(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        adapter = self.serve((200, calendar("1@test"), {"ETag": '"v1"'}))
+
+        cache(entry)
+        os.remove(os.path.join("app", "cache", cache_key(entry, ".ics")))
+        cache(entry)
+
+        self.assertNotIn("If-None-Match", adapter.requests[1].headers)
+        self.assertIsNotNone(backend().meta(cache_key(entry, ".ics")))
+
+    def test_expired_calendar_is_renewed_once_the_scheduler_is_late(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        adapter = self.serve((200, calendar("1@test"), {}), (200, calendar("1@test", "2@test"), {}))
+
+        cache(entry)
+        self.clock.advance(minutes=10, seconds=30)
+        self.assertEqual(len(get_from_cache(entry).events), 1)
+        self.assertEqual(len(adapter.requests), 1)
+
+        self.clock.advance(minutes=1)
+        self.assertEqual(len(get_from_cache(entry).events), 2)
+        self.assertEqual(len(adapter.requests), 2)
+
+    def test_last_good_copy_is_written_when_the_content_changes(self):
+        entry = {"name": "test", "url": URL}
+        self.serve((200, calendar("1@test"), {}), (200, calendar("1@test"), {}), (200, calendar("2@test"), {}))
+        last_good = cache_key(entry, ".last.ics")
+
+        load_cal(entry)
+        backend().put(last_good, "unchanged")
+        load_cal(entry)
+        self.assertEqual(backend().get(last_good), "unchanged")
+
+        load_cal(entry)
+        self.assertIn("UID:2@test", backend().get(last_good))
+
+    def test_last_good_copy_is_served_when_the_download_fails(self):
+        entry = {"name": "test", "url": URL}
+        self.serve((200, calendar("1@test"), {}), (500, "Internal Server Error", {}))
+
+        load_cal(entry)
+        self.clock.advance(minutes=30)
+        cal = load_cal(entry)
+
+        self.assertEqual([event.uid for event in cal.events], ["1@test"])
+        self.assertEqual(next(iter(cal.events)).description,
+                         "Source unavailable, last downloaded at 2021-01-01 00:00:00")
+        self.assertEqual(read_status(entry)["consecutiveFailures"], 1)
+
+    def test_calendar_removed_from_the_full_cache_is_cached_again_when_requested(self):
+        first = {"name": "first", "url": URL, "cache": 10}
+        second = {"name": "second", "url": "https://calendar.example.com/other.ics", "cache": 10}
+        os.mkdir(os.path.join("app", "config"))
+        with open(os.path.join("app", "config", "feeds.json"), "w") as file:
+            json.dump([first, second], file)
+        session, adapter = fake_transport({first["url"]: (200, calendar("1@test"), {}),
+                                           second["url"]: (200, calendar("2@test"), {})})
+        set_transport(session)
+
+        cache(first)
+        cache(second)
+        self.clock.advance(minutes=1)
+        get_from_cache(first)
+        set_cache_limits(max_entries=1)
+        prune()
+
+        self.assertIsNone(backend().meta(cache_key(second, ".ics")))
+        self.assertEqual([event.uid for event in get_from_cache(second).events], ["2@test"])
+        self.assertEqual(len(adapter.requests), 3)
+
+    def test_snapshots_are_kept_within_their_count(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "snapshots": {"count": 2}}
+        self.serve((200, calendar("1@test"), {}), (200, calendar("1@test"), {}), (200, calendar("2@test"), {}),
+                   (200, calendar("3@test"), {}))
+
+        for _ in range(4):
+            cache(entry)
+            self.clock.advance(minutes=1)
+
+        kept = snapshots(cache_key(entry, ""))
+        self.assertEqual([snapshot_time(key) for key in kept], [1609459320, 1609459380])
+        self.assertIn("UID:3@test", backend().get(kept[1]))
+
+    def test_snapshots_older_than_their_max_age_are_removed(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "snapshots": {"maxAge": 1}}
+        self.serve((200, calendar("1@test"), {}), (200, calendar("2@test"), {}))
+
+        cache(entry)
+        self.clock.advance(days=2)
+        cache(entry)
+
+        self.assertEqual([snapshot_time(key) for key in snapshots(cache_key(entry, ""))], [1609632000])
+
+    def test_restored_snapshot_is_served_until_the_remote_changes(self):
+        entry = {"name": "test", "url": URL, "cache": 10, "snapshots": {"count": 5}}
+        self.serve((200, calendar("1@test"), {}), (200, calendar("2@test"), {}), (200, calendar("2@test"), {}),
+                   (200, calendar("3@test"), {}))
+
+        cache(entry)
+        self.clock.advance(minutes=1)
+        cache(entry)
+        restore(cache_key(entry, ""), 1609459200)
+        self.assertEqual(self.uids(entry), ["1@test"])
+
+        cache(entry)
+        self.assertEqual(self.uids(entry), ["1@test"])
+
+        cache(entry)
+        self.assertEqual(self.uids(entry), ["3@test"])
+        self.assertNotIn("restoredOver", read_status(entry))
+
+    def test_restore_of_a_missing_snapshot(self):
+        with self.assertRaises(KeyError):
+            restore(cache_key({"url": URL}, ""), 1609459200)
+
+    def test_export_then_import(self):
+        entry = {"name": "test", "url": URL, "cache": 10}
+        self.serve((200, calendar("1@test"), {"ETag": '"v1"'}))
+        cache(entry)
+        key = cache_key(entry, ".ics")
+        data, meta = backend().get(key), backend().meta(key)
+
+        self.assertEqual(export_cache("cache.gz"), 2)
+        backend().delete(key)
+        self.clock.advance(minutes=5)
+        self.assertEqual(import_cache("cache.gz"), 2)
+
+        self.assertEqual(backend().get(key), data)
+        self.assertEqual(backend().meta(key)["etag"], meta["etag"])
+        self.assertEqual(age(entry), 0)
+
+    def test_import_of_another_file(self):
+        with gzip.open("cache.gz", "wt") as archive:
+            archive.write(json.dumps({"format": "other"}) + "\n")
+
+        with self.assertRaises(ValueError):
+            import_cache("cache.gz")
+
+    def test_import_of_a_file_outside_of_the_cache(self):
+        with gzip.open("cache.gz", "wt") as archive:
+            archive.write(json.dumps({"format": "ics-fusion-cache", "version": 1}) + "\n")
+            archive.write(json.dumps({"key": "../config/feeds.json", "data": "[]", "meta": {}}) + "\n")
+
+        with self.assertRaises(ValueError):
+            import_cache("cache.gz")
+        self.assertFalse(os.path.exists(os.path.join("app", "config", "feeds.json")))
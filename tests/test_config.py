@@ -0,0 +1,34 @@
+import json
+import os
+import tempfile
+import unittest
+from unittest import mock
+
+from tools import config
+from tools.config import read_config, read_options
+
+
+class ConfigTest(unittest.TestCase):
+    def setUp(self):
+        self.directory = tempfile.TemporaryDirectory()
+        patcher = mock.patch.object(config, "CONFIG_DIR", self.directory.name)
+        patcher.start()
+        self.addCleanup(patcher.stop)
+        self.addCleanup(self.directory.cleanup)
+
+    def write(self, name: str, content):
+        with open(os.path.join(self.directory.name, name), "w") as file:
+            json.dump(content, file)
+
+    def test_list_of_entries(self):
+        self.write("work.json", [{"name": "team", "url": "https://calendar.example.com/team.ics"}])
+
+        self.assertEqual([entry["name"] for entry in read_config("work.json")], ["team"])
+        self.assertEqual(read_options("work.json"), {})
+
+    def test_entries_with_options(self):
+        self.write("work.json", {"calendar": {"conflicts": "modified"},
+                                 "sources": [{"name": "team", "url": "https://calendar.example.com/team.ics"}]})
+
+        self.assertEqual([entry["name"] for entry in read_config("work.json")], ["team"])
+        self.assertEqual(read_options("work.json"), {"conflicts": "modified"})
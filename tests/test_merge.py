@@ -0,0 +1,74 @@
+import unittest
+
+from ics import Calendar, Event
+from ics.grammar.parse import ContentLine
+
+from tools.recurrence import override
+from tools.tools import merge
+
+
+def event(uid: str, name: str, *lines: str, begin: str = "2020-01-01T10:00:00+00:00") -> Event:
+    result = Event(name=name, begin=begin, uid=uid)
+    for line in lines:
+        key, value = line.split(":", 1)
+        result.extra.append(ContentLine(name=key, value=value))
+
+    return result
+
+
+def calendar(*events: Event) -> Calendar:
+    result = Calendar()
+    result.events = set(events)
+    return result
+
+
+class MergeTest(unittest.TestCase):
+    def test_highest_sequence_is_kept(self):
+        merged = merge([calendar(event("1@test", "Old", "SEQUENCE:1")),
+                        calendar(event("1@test", "New", "SEQUENCE:2"))])
+
+        self.assertEqual([e.name for e in merged.events], ["New"])
+
+    def test_first_calendar_wins_on_the_same_sequence(self):
+        merged = merge([calendar(event("1@test", "First")), calendar(event("1@test", "Second", "SEQUENCE:0"))])
+
+        self.assertEqual([e.name for e in merged.events], ["First"])
+
+    def test_overrides_are_kept_apart(self):
+        merged = merge([calendar(event("1@test", "Series", "RRULE:FREQ=DAILY"),
+                                 override(event("1@test", "Moved", "RECURRENCE-ID:20200102T100000Z"))),
+                        calendar(event("1@test", "Series", "RRULE:FREQ=DAILY"))])
+
+        self.assertEqual(sorted(e.name for e in merged.events), ["Moved", "Series"])
+
+    def test_only_calendars_are_merged(self):
+        with self.assertRaises(ValueError):
+            merge([calendar(), "BEGIN:VCALENDAR"])
+
+    def test_last_modified_version_is_kept(self):
+        merged = merge([calendar(event("1@test", "Old", "SEQUENCE:3", "LAST-MODIFIED:20200101T000000Z")),
+                        calendar(event("1@test", "New", "DTSTAMP:20200102T000000Z"))], "modified")
+
+        self.assertEqual([e.name for e in merged.events], ["New"])
+
+    def test_first_calendar_has_priority(self):
+        merged = merge([calendar(event("1@test", "First")), calendar(event("1@test", "Second", "SEQUENCE:2"))],
+                       "priority")
+
+        self.assertEqual([e.name for e in merged.events], ["First"])
+
+    def test_unknown_strategy(self):
+        with self.assertRaises(ValueError):
+            merge([calendar(event("1@test", "First"))], "newest")
+
+    def test_similar_events_of_other_calendars_are_dropped(self):
+        merged = merge([calendar(event("1@be", "New Year's Day")), calendar(event("1@fr", "New year day"))],
+                       similarity=0.8)
+
+        self.assertEqual([e.uid for e in merged.events], ["1@be"])
+
+    def test_similar_events_are_kept_in_the_same_calendar_or_at_other_times(self):
+        merged = merge([calendar(event("1@test", "Stand-up"), event("2@test", "Stand up")),
+                        calendar(event("3@test", "Stand-up", begin="2020-01-02T10:00:00+00:00"))], similarity=0.8)
+
+        self.assertEqual(sorted(e.uid for e in merged.events), ["1@test", "2@test", "3@test"])
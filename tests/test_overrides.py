@@ -0,0 +1,93 @@
+import unittest
+from datetime import datetime
+
+import arrow
+from dateutil import tz
+
+from tools.clock import Clock, FixedClock, set_clock
+from tools.properties import parse_calendar
+from tools.recurrence import identity
+from tools.tools import apply_filters, apply_modify, expand, merge, overrides
+
+
+def calendar(*events: str) -> str:
+    return "\r\n".join(["BEGIN:VCALENDAR", "VERSION:2.0", "PRODID:-//test//test//EN"] + list(events)
+                       + ["END:VCALENDAR"])
+
+
+MASTER = "\r\n".join([
+    "BEGIN:VEVENT",
+    "UID:1@test",
+    "DTSTAMP:20200101T000000Z",
+    "DTSTART:20200101T100000Z",
+    "DTEND:20200101T110000Z",
+    "SUMMARY:Standup",
+    "DESCRIPTION:Daily meeting",
+    "RRULE:FREQ=DAILY;COUNT=5",
+    "END:VEVENT",
+])
+OVERRIDE = "\r\n".join([
+    "BEGIN:VEVENT",
+    "UID:1@test",
+    "DTSTAMP:20200101T000000Z",
+    "RECURRENCE-ID:20200103T100000Z",
+    "DTSTART:20200103T150000Z",
+    "DTEND:20200103T160000Z",
+    "SUMMARY:Standup (moved)",
+    "DESCRIPTION:Daily meeting",
+    "END:VEVENT",
+])
+UPDATED = MASTER.replace("RRULE:", "SEQUENCE:1\r\nRRULE:")
+
+
+class OverridesTest(unittest.TestCase):
+    def test_parse(self):
+        cal = parse_calendar(calendar(MASTER, OVERRIDE))
+
+        self.assertEqual({identity(event) for event in cal.events},
+                         {("1@test", None), ("1@test", "20200103T100000Z")})
+
+    def test_merge_duplicated_calendar(self):
+        cal = merge([parse_calendar(calendar(MASTER, OVERRIDE)), parse_calendar(calendar(MASTER, OVERRIDE))])
+
+        self.assertEqual(len(cal.events), 2)
+
+    def test_merge_override_of_outdated_master(self):
+        cal = merge([parse_calendar(calendar(MASTER, OVERRIDE)), parse_calendar(calendar(UPDATED))])
+
+        self.assertEqual([identity(event) for event in cal.events], [("1@test", None)])
+        self.assertIn("SEQUENCE", [line.name for line in list(cal.events)[0].extra])
+
+    def test_filter_keeps_override_with_master(self):
+        cal = apply_filters(parse_calendar(calendar(MASTER, OVERRIDE)), {"name": {"includeOnly": "Standup$"}})
+
+        self.assertEqual(len(cal.events), 2)
+
+    def test_filter_drops_override_with_master(self):
+        cal = apply_filters(parse_calendar(calendar(MASTER, OVERRIDE)), {"name": {"exclude": "Standup$"}})
+
+        self.assertEqual(len(cal.events), 0)
+
+    def test_modify_uid(self):
+        cal = apply_modify(parse_calendar(calendar(MASTER, OVERRIDE)), {"uid": {"addPrefix": "work-"}})
+
+        self.assertEqual({identity(event) for event in cal.events},
+                         {("work-1@test", None), ("work-1@test", "20200103T100000Z")})
+
+    def test_expand(self):
+        set_clock(FixedClock(arrow.get(datetime(2020, 1, 2, tzinfo=tz.tzutc()))))
+        try:
+            cal = expand(parse_calendar(calendar(MASTER, OVERRIDE)), {"pastDays": 5, "futureDays": 10})
+        finally:
+            set_clock(Clock())
+
+        starts = sorted((event.begin.datetime.day, event.begin.datetime.hour) for event in cal.events)
+        self.assertEqual(starts, [(1, 10), (2, 10), (3, 15), (4, 10), (5, 10)])
+        self.assertEqual(len({event.uid for event in cal.events}), 5)
+
+    def test_malformed_recurrence_id(self):
+        malformed = OVERRIDE.replace("RECURRENCE-ID:20200103T100000Z", "RECURRENCE-ID:tomorrow")
+        cal = parse_calendar(calendar(MASTER, malformed))
+
+        self.assertEqual(overrides(cal.events), {})
+        self.assertEqual(len(merge([cal]).events), 2)
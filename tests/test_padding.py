@@ -0,0 +1,44 @@
+import unittest
+
+from tools.properties import parse_calendar
+from tools.recurrence import identity
+from tools.tools import Padding
+
+CALENDAR = "\r\n".join([
+    "BEGIN:VCALENDAR",
+    "VERSION:2.0",
+    "PRODID:-//test//test//EN",
+    "BEGIN:VEVENT",
+    "UID:1@test",
+    "DTSTART:20200101T100000Z",
+    "DTEND:20200101T110000Z",
+    "SUMMARY:Visit",
+    "LOCATION:Brussels",
+    "RRULE:FREQ=DAILY;COUNT=5",
+    "EXDATE:20200105T100000Z",
+    "END:VEVENT",
+    "BEGIN:VEVENT",
+    "UID:1@test",
+    "RECURRENCE-ID:20200103T100000Z",
+    "DTSTART:20200103T150000Z",
+    "DTEND:20200103T160000Z",
+    "SUMMARY:Visit",
+    "LOCATION:Brussels",
+    "END:VEVENT",
+    "END:VCALENDAR",
+])
+
+
+class PaddingTest(unittest.TestCase):
+    def test_buffers_of_overrides(self):
+        cal = Padding({"before": 30}).apply(parse_calendar(CALENDAR))
+        buffers = {event.uid: event for event in cal.events if event.name == "Travel"}
+
+        self.assertEqual(len(cal.events), 4)
+        self.assertEqual(set(buffers), {"before-1@test", "before-1@test-20200103T100000Z"})
+        self.assertEqual(buffers["before-1@test-20200103T100000Z"].begin.datetime.hour, 14)
+
+        master = buffers["before-1@test"]
+        self.assertEqual(identity(master), ("before-1@test", None))
+        self.assertEqual([line.value for line in master.extra if line.name == "EXDATE"],
+                         ["20200103T093000Z,20200105T093000Z"])
@@ -0,0 +1,84 @@
+import unittest
+
+from tools.properties import QuotedLine, clone, parse_calendar, parse_line, string_to_container
+
+CALENDAR = "\r\n".join([
+    "BEGIN:VCALENDAR",
+    "VERSION:2.0",
+    "PRODID:-//test//test//EN",
+    "BEGIN:VEVENT",
+    "UID:1@test",
+    "DTSTAMP:20200101T000000Z",
+    "DTSTART:20200101T100000Z",
+    "DTEND:20200101T110000Z",
+    'X-APPLE-STRUCTURED-LOCATION;VALUE=URI;X-TITLE="Room 1: Brussels; 2nd',
+    '  floor":geo:50.8,4.3',
+    "END:VEVENT",
+    "END:VCALENDAR",
+])
+
+
+class ParseLineTest(unittest.TestCase):
+    def test_comma_in_quoted_value(self):
+        line = parse_line('ATTENDEE;CN="Doe, John";ROLE=REQ-PARTICIPANT:mailto:john@example.com')
+
+        self.assertEqual(line.name, "ATTENDEE")
+        self.assertEqual(line.params["CN"], ["Doe, John"])
+        self.assertEqual(line.params["ROLE"], ["REQ-PARTICIPANT"])
+        self.assertEqual(line.value, "mailto:john@example.com")
+        self.assertEqual(str(line), 'ATTENDEE;CN="Doe, John";ROLE=REQ-PARTICIPANT:mailto:john@example.com')
+
+    def test_colon_and_semicolon_in_quoted_value(self):
+        text = 'X-APPLE-STRUCTURED-LOCATION;VALUE=URI;X-TITLE="Room 1: Brussels; 2nd floor":geo:50.8,4.3'
+        line = parse_line(text)
+
+        self.assertEqual(line.params["X-TITLE"], ["Room 1: Brussels; 2nd floor"])
+        self.assertEqual(line.value, "geo:50.8,4.3")
+        self.assertEqual(str(line), text)
+
+    def test_quotes_kept_without_special_characters(self):
+        text = 'ORGANIZER;CN="Jane";SENT-BY="mailto:jane@example.com":mailto:boss@example.com'
+
+        self.assertEqual(str(parse_line(text)), text)
+
+    def test_several_values(self):
+        text = 'ATTENDEE;DELEGATED-TO="mailto:a@example.com","mailto:b@example.com":mailto:c@example.com'
+        line = parse_line(text)
+
+        self.assertEqual(line.params["DELEGATED-TO"], ["mailto:a@example.com", "mailto:b@example.com"])
+        self.assertEqual(str(line), text)
+
+    def test_value_kept_as_is(self):
+        self.assertEqual(parse_line("DESCRIPTION:trailing space ").value, "trailing space ")
+
+    def test_added_value_quoted(self):
+        line = QuotedLine("ATTENDEE", {"CN": ["Doe, John"]}, "mailto:john@example.com")
+
+        self.assertEqual(str(line), 'ATTENDEE;CN="Doe, John":mailto:john@example.com')
+
+    def test_clone(self):
+        line = parse_line('ATTENDEE;CN="Doe, John":mailto:john@example.com')
+        copy = clone(line)
+        copy.params["CN"].append("Other")
+
+        self.assertEqual(str(line), 'ATTENDEE;CN="Doe, John":mailto:john@example.com')
+        self.assertEqual(str(clone(line)), str(line))
+
+
+class ParseCalendarTest(unittest.TestCase):
+    def test_nesting_and_unfolding(self):
+        items = string_to_container(CALENDAR)
+
+        self.assertEqual(len(items), 1)
+        self.assertEqual(items[0].name, "VCALENDAR")
+        event = [item for item in items[0] if item.name == "VEVENT"][0]
+        location = [line for line in event if line.name == "X-APPLE-STRUCTURED-LOCATION"][0]
+        self.assertEqual(location.params["X-TITLE"], ["Room 1: Brussels; 2nd floor"])
+
+    def test_round_trip(self):
+        cal = parse_calendar(CALENDAR)
+        event = list(cal.events)[0]
+        location = [line for line in event.extra if line.name == "X-APPLE-STRUCTURED-LOCATION"][0]
+
+        self.assertEqual(str(location),
+                         'X-APPLE-STRUCTURED-LOCATION;VALUE=URI;X-TITLE="Room 1: Brussels; 2nd floor":geo:50.8,4.3')
@@ -0,0 +1,52 @@
+import unittest
+from datetime import datetime
+from types import SimpleNamespace
+
+import arrow
+from dateutil import tz
+
+from tools.properties import parse_line
+from tools.recurrence import occurrences, until
+
+
+def event(begin: datetime, end: datetime, *lines: str) -> SimpleNamespace:
+    return SimpleNamespace(uid="1@test", begin=arrow.get(begin), end=arrow.get(end),
+                           extra=[parse_line(line) for line in lines])
+
+
+class UntilTest(unittest.TestCase):
+    def test_date(self):
+        start = datetime(2020, 1, 1, tzinfo=tz.tzutc())
+
+        self.assertEqual(until("FREQ=DAILY;UNTIL=20200105", start), "FREQ=DAILY;UNTIL=20200105T235959Z")
+
+    def test_local_time(self):
+        start = datetime(2020, 1, 1, 10, tzinfo=tz.gettz("Europe/Brussels"))
+
+        self.assertEqual(until("FREQ=DAILY;UNTIL=20200105T100000;COUNT=3", start),
+                         "FREQ=DAILY;UNTIL=20200105T090000Z;COUNT=3")
+
+    def test_utc(self):
+        start = datetime(2020, 1, 1, 10, tzinfo=tz.gettz("Europe/Brussels"))
+
+        self.assertEqual(until("FREQ=DAILY;UNTIL=20200105T090000Z", start), "FREQ=DAILY;UNTIL=20200105T090000Z")
+
+
+class OccurrencesTest(unittest.TestCase):
+    def test_all_day_until_date(self):
+        utc = tz.tzutc()
+        day = event(datetime(2020, 1, 1, tzinfo=utc), datetime(2020, 1, 2, tzinfo=utc),
+                    "RRULE:FREQ=DAILY;UNTIL=20200105")
+        found = occurrences(day, arrow.get(datetime(2019, 12, 1, tzinfo=utc)),
+                            arrow.get(datetime(2020, 2, 1, tzinfo=utc)))
+
+        self.assertEqual([b.datetime.day for b, e in found], [1, 2, 3, 4, 5])
+
+    def test_local_until_across_dst(self):
+        brussels = tz.gettz("Europe/Brussels")
+        weekly = event(datetime(2020, 3, 20, 10, tzinfo=brussels), datetime(2020, 3, 20, 11, tzinfo=brussels),
+                       "RRULE:FREQ=WEEKLY;UNTIL=20200403T100000")
+        found = occurrences(weekly, arrow.get(datetime(2020, 3, 1, tzinfo=brussels)),
+                            arrow.get(datetime(2020, 5, 1, tzinfo=brussels)))
+
+        self.assertEqual([(b.datetime.day, b.datetime.hour) for b, e in found], [(20, 10), (27, 10), (3, 10)])
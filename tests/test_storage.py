@@ -0,0 +1,201 @@
+import base64
+import sqlite3
+import tempfile
+import unittest
+from unittest import mock
+
+import arrow
+
+from tools import storage
+from tools.clock import Clock, FixedClock, set_clock
+from tools.storage import MIGRATIONS, ZSTD_MAGIC, EncryptedBackend, FileBackend, MemoryBackend, RedisBackend, \
+    SQLiteBackend, compress, decompress, open_backend
+
+
+def close(backend: SQLiteBackend) -> None:
+    while not backend.pool.empty():
+        backend.pool.get().close()
+
+
+class FakeRedis:
+    """Redis client keeping the hashes in memory
+    """
+
+    def __init__(self):
+        self.hashes = {}
+
+    def hget(self, name, key):
+        value = self.hashes.get(name, {}).get(key)
+        return str(value) if value is not None else None
+
+    def hmget(self, name, *keys):
+        return [self.hget(name, key) for key in keys]
+
+    def hset(self, name, key=None, value=None, mapping=None):
+        self.hashes.setdefault(name, {}).update(mapping or {key: value})
+
+    def hdel(self, name, *keys):
+        for key in keys:
+            self.hashes.get(name, {}).pop(key, None)
+
+    def hstrlen(self, name, key):
+        return len(self.hget(name, key) or "")
+
+    def delete(self, name):
+        self.hashes.pop(name, None)
+
+    def scan_iter(self, pattern):
+        return [name for name in self.hashes if name.startswith(pattern.rstrip("*"))]
+
+    def pipeline(self):
+        return self
+
+    def execute(self):
+        pass
+
+
+class BackendTest(unittest.TestCase):
+    def setUp(self):
+        self.clock = FixedClock(arrow.get("2021-01-01T00:00:00+00:00"))
+        set_clock(self.clock)
+
+    def tearDown(self):
+        set_clock(Clock())
+
+    def check(self, backend, size=15):
+        self.assertIsNone(backend.get("feed.ics"))
+        self.assertIsNone(backend.meta("feed.ics"))
+
+        backend.put("feed.ics", "BEGIN:VCALENDAR", {"etag": '"v1"', "lastModified": None})
+        self.assertEqual(backend.get("feed.ics"), "BEGIN:VCALENDAR")
+        self.assertEqual(backend.meta("feed.ics"), {"mtime": self.clock.now().timestamp, "size": size, "etag": '"v1"'})
+
+        self.clock.advance(minutes=10)
+        backend.touch("feed.ics")
+        self.assertEqual(backend.meta("feed.ics"), {"mtime": self.clock.now().timestamp, "size": size, "etag": '"v1"'})
+
+        backend.touch("feed.ics", {"contentHash": "abc"})
+        self.assertEqual(backend.meta("feed.ics"),
+                         {"mtime": self.clock.now().timestamp, "size": size, "contentHash": "abc"})
+
+    def test_file_backend(self):
+        with tempfile.TemporaryDirectory() as directory:
+            self.check(FileBackend(directory + "/cache"))
+
+    def test_memory_backend(self):
+        self.check(open_backend("memory://"))
+
+    def test_sqlite_backend(self):
+        with tempfile.TemporaryDirectory() as directory:
+            backend = open_backend("sqlite:///" + directory + "/cache.db")
+            # The calendars are compressed in the database
+            self.check(backend, len(compress("BEGIN:VCALENDAR")))
+            close(backend)
+
+    def test_redis_backend(self):
+        client = FakeRedis()
+        with mock.patch.object(storage, "redis") as redis:
+            redis.Redis.from_url.return_value = client
+            backend = open_backend("redis://localhost:6379/0")
+
+        redis.Redis.from_url.assert_called_once_with("redis://localhost:6379/0", decode_responses=True)
+        self.check(backend)
+        self.assertEqual(set(client.hashes), {"ics-fusion:feed.ics"})
+
+    def test_redis_module_is_required(self):
+        with mock.patch.object(storage, "redis", None), self.assertRaises(RuntimeError):
+            RedisBackend("redis://localhost:6379/0")
+
+    def test_directory_backend(self):
+        self.assertEqual(open_backend("/var/cache/ics").directory, "/var/cache/ics")
+
+    def test_compression(self):
+        self.assertEqual(decompress(compress("BEGIN:VCALENDAR")), "BEGIN:VCALENDAR")
+        self.assertEqual(decompress("BEGIN:VCALENDAR"), "BEGIN:VCALENDAR")
+
+    def test_zstandard_module_is_required_to_read_zstd(self):
+        with mock.patch.object(storage, "zstandard", None), self.assertRaises(RuntimeError):
+            decompress(ZSTD_MAGIC + b"compressed")
+
+
+class MigrationTest(unittest.TestCase):
+    def setUp(self):
+        self.directory = tempfile.TemporaryDirectory()
+        self.path = self.directory.name + "/cache.db"
+
+    def tearDown(self):
+        self.directory.cleanup()
+
+    def test_database_without_version_is_migrated(self):
+        connection = sqlite3.connect(self.path)
+        with connection:
+            connection.execute("CREATE TABLE cache (key TEXT PRIMARY KEY, data TEXT, mtime REAL)")
+            connection.execute("INSERT INTO cache VALUES ('feed.ics', 'BEGIN:VCALENDAR', 1609459200)")
+        connection.close()
+
+        backend = SQLiteBackend(self.path)
+        with backend.connection() as connection:
+            self.assertEqual(backend.version(connection), len(MIGRATIONS))
+        self.assertEqual(backend.get("feed.ics"), "BEGIN:VCALENDAR")
+        self.assertEqual(backend.meta("feed.ics")["mtime"], 1609459200)
+        close(backend)
+
+    def test_migrated_database_is_opened_again(self):
+        close(SQLiteBackend(self.path))
+        backend = SQLiteBackend(self.path)
+        with backend.connection() as connection:
+            self.assertEqual(backend.version(connection), len(MIGRATIONS))
+            self.assertEqual(connection.execute("PRAGMA journal_mode").fetchone()[0], "wal")
+        close(backend)
+
+    def test_database_of_a_newer_version_is_refused(self):
+        connection = sqlite3.connect(self.path)
+        with connection:
+            connection.execute("CREATE TABLE schema_version (version INTEGER)")
+            connection.execute("INSERT INTO schema_version VALUES (?)", (len(MIGRATIONS) + 1,))
+        connection.close()
+
+        with self.assertRaises(RuntimeError):
+            SQLiteBackend(self.path)
+
+
+@unittest.skipIf(storage.ChaCha20Poly1305 is None, "the cryptography module is not installed")
+class EncryptionTest(unittest.TestCase):
+    KEY = base64.b64encode(bytes(range(32))).decode()
+
+    def setUp(self):
+        self.storage = MemoryBackend()
+        self.backend = EncryptedBackend(self.storage, self.KEY)
+
+    def test_files_are_encrypted(self):
+        self.backend.put("feed.ics", "BEGIN:VCALENDAR")
+        self.assertNotIn("VCALENDAR", self.storage.get("feed.ics"))
+        self.assertEqual(self.backend.get("feed.ics"), "BEGIN:VCALENDAR")
+
+    def test_other_key_cannot_decrypt(self):
+        self.backend.put("feed.ics", "BEGIN:VCALENDAR")
+        other = EncryptedBackend(self.storage, base64.b64encode(bytes(32)).decode())
+        self.assertIsNone(other.get("feed.ics"))
+
+    def test_swapped_files_cannot_be_decrypted(self):
+        self.backend.put("feed.ics", "BEGIN:VCALENDAR")
+        self.storage.put("other.ics", self.storage.get("feed.ics"))
+        self.assertIsNone(self.backend.get("other.ics"))
+
+    def test_plaintext_files(self):
+        self.storage.put("feed.ics", "BEGIN:VCALENDAR")
+        self.assertIsNone(self.backend.get("feed.ics"))
+        self.assertEqual(EncryptedBackend(self.storage, self.KEY, plaintext=True).get("feed.ics"), "BEGIN:VCALENDAR")
+
+    def test_invalid_keys(self):
+        for key in ("not base64!", base64.b64encode(bytes(16)).decode()):
+            with self.assertRaises(ValueError):
+                EncryptedBackend(self.storage, key)
+
+    def test_open_encrypted_backend(self):
+        self.assertIsInstance(open_backend("memory://", self.KEY), EncryptedBackend)
+        self.assertIsInstance(open_backend("memory://"), MemoryBackend)
+
+    def test_cryptography_module_is_required(self):
+        with mock.patch.object(storage, "ChaCha20Poly1305", None), self.assertRaises(RuntimeError):
+            EncryptedBackend(self.storage, self.KEY)
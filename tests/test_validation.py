@@ -0,0 +1,23 @@
+import unittest
+
+from ics import Calendar, Event
+
+from tools.validation import fold, serialize, validate
+
+
+class SerializeTest(unittest.TestCase):
+    def test_long_lines_are_folded(self):
+        line = "DESCRIPTION:" + "é" * 100
+
+        physical = fold(line)
+        self.assertTrue(all(len(part.encode()) <= 75 for part in physical))
+        self.assertTrue(all(part.startswith(" ") for part in physical[1:]))
+        self.assertEqual(physical[0] + "".join(part[1:] for part in physical[1:]), line)
+
+    def test_served_calendar_is_valid(self):
+        cal = Calendar()
+        cal.events.add(Event(name="Meeting " * 20, begin="2021-01-01T10:00:00+00:00", uid="1@test"))
+
+        data = serialize(cal)
+        self.assertTrue(data.endswith("END:VCALENDAR\r\n"))
+        self.assertEqual([problem for problem in validate(data) if "octets" in problem or "CRLF" in problem], [])
@@ -0,0 +1,72 @@
+"""HTTP transport of the tests: the session of the application, with an adapter answering from a table of responses
+instead of the network and recording the requests it receives
+"""
+
+import http.client
+import io
+from types import SimpleNamespace
+
+from requests.adapters import BaseAdapter
+from requests.cookies import extract_cookies_to_jar
+from requests.models import Response
+from requests.structures import CaseInsensitiveDict
+
+from tools.fetchers import new_session
+
+
+class FakeAdapter(BaseAdapter):
+    def __init__(self, responses: dict):
+        """
+        :param responses: the answers by URL: a (status, body, headers) tuple, a list of such tuples served in turn,
+        or an exception raised when the URL is requested
+        :type responses: dict
+        """
+
+        super().__init__()
+        self.responses = responses
+        self.requests = []
+
+    def send(self, request, **kwargs):
+        self.requests.append(request)
+        answer = self.responses[request.url]
+        if isinstance(answer, list):
+            answer = answer.pop(0) if len(answer) > 1 else answer[0]
+        if isinstance(answer, Exception):
+            raise answer
+
+        status, body, headers = answer
+        response = Response()
+        response.status_code = status
+        # The content is read from the raw stream, as for a streamed response
+        response.raw = io.BytesIO(body.encode() if isinstance(body, str) else body)
+        response.headers = CaseInsensitiveDict(headers)
+        response.url = request.url
+        response.request = request
+
+        # The cookies are read from the raw response, as by the HTTP adapter of requests
+        message = http.client.HTTPMessage()
+        for name, value in headers.items():
+            message[name] = value
+        response.raw._original_response = SimpleNamespace(msg=message)
+        extract_cookies_to_jar(response.cookies, request, response.raw)
+        return response
+
+    def close(self):
+        pass
+
+
+def fake_transport(responses: dict):
+    """
+    :param responses: the answers by URL, see FakeAdapter
+    :type responses: dict
+
+
+    :return: the session of the application, answered by the adapter, to give to set_transport, and the adapter
+    recording its requests
+    """
+
+    adapter = FakeAdapter(responses)
+    session = new_session()
+    session.mount("http://", adapter)
+    session.mount("https://", adapter)
+    return session, adapter